@@ -0,0 +1,3391 @@
+//! # any_anagram
+//! Searches a wordlist for the phrases that are anagrams of a target phrase
+//! and whose MD5 digest matches one of the checksums being looked for.
+//! The command line in main.rs is a thin layer over AnagramSearchBuilder.
+//!
+//! The traversal logs every word it tries and backtracks from through the log
+//! crate at trace level, with the target any_anagram. Nothing is logged until
+//! the caller installs a logger, the way main.rs does for --verbose.
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::ops::Index;
+use async_std::task;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::time::{Duration, Instant};
+use std::cmp::{Ordering, Reverse};
+use num_format::{Locale, ToFormattedString};
+use regex::Regex;
+use rayon::prelude::*;
+use log::trace;
+use std::fmt;
+use std::str::FromStr;
+use serde::Serialize;
+
+
+// Print a line of the search's output after the options' output prefix.
+macro_rules! say {
+    ($prefix:expr, $($arg:tt)*) => {
+        print_prefixed($prefix, &format!($($arg)*))
+    };
+}
+
+// Print text a line at a time, each after the prefix. Holding stdout for all
+// of it keeps the lines of another search printing at the same time out.
+fn print_prefixed(prefix: &str, text: &str) {
+    let mut stdout = std::io::stdout().lock();
+    for line in text.lines() {
+        writeln!(stdout, "{}{}", prefix, line).ok();
+    }
+}
+
+// Exit codes, 0 means every checksum was solved.
+pub const EXIT_UNSOLVED: i32 = 1; // Default when the search ends with checksums left unsolved
+pub const EXIT_ERROR: i32 = 2; // Bad options or files that can't be read
+
+/// Options read from the command line. Everything is off by default so
+/// running without arguments behaves the same as it always has.
+#[derive(Clone, Debug)]
+pub struct AnagramOptions {
+    pub target: Option<String>, // The anagram phrase given on the command line
+    pub combined: Option<String>, // A file holding both the anagram phrase and the wordlist
+    pub wordlist: Option<String>, // The wordlist to search instead of resources/wordlist
+    pub checksum_bin: Vec<String>, // Files holding raw digests to search for
+    pub uppercase_hash: bool, // Hash the phrase uppercased, it's still printed as it was built
+    pub print_digests: bool, // Print the digest of every completed phrase, not only the matches
+    pub prefix_match: usize, // Report phrases whose digest starts with this many hex characters of a checksum, 0 for none
+    pub stream: bool, // Drop unusable words while reading the wordlist to save memory
+    pub index: Option<String>, // An index written by write_index to read each root's sorted anagrams from instead of loading a wordlist
+    pub write_index: Option<String>, // Where to write the wordlist as an index for --index
+    pub parallel_load: bool, // Build the map of sorted words on every core
+    pub compact_keys: bool, // Key the loaded wordlist by letter counts, EG: a3bn2, instead of sorted letters
+    pub analyze_wordlist: bool, // Print statistics about the wordlist and exit without searching
+    pub repl: bool, // Read targets and checksums from stdin against a wordlist loaded once
+    pub profile: bool, // Time each phase of the search and print where the time went
+    pub metrics_out: Option<String>, // Where to write the final metrics as JSON
+    pub dot: Option<String>, // Where to write the search tree as a Graphviz DOT graph, for small searches
+    pub show_target_key: bool, // Print the target's sorted key and letter counts before searching
+    pub no_word_order: bool, // Build each set of words once, in the order they were collected, instead of every ordering
+    pub count_only: bool, // Count the phrases instead of building and hashing them
+    pub stream_solutions: bool, // Print solutions as they arrive instead of only at the end
+    pub sort_by_time: bool, // Print the solutions at the end in the order they were found
+    pub all_matches: bool, // Keep matching a checksum after it's solved and search every root
+    pub dump_excluded: Option<String>, // Where to write the words excluded by the character filter
+    pub phrase_allow: Vec<Regex>, // A phrase must match all of these to be considered
+    pub phrase_deny: Vec<Regex>, // A phrase must match none of these to be considered
+    pub first_letter: Option<char>, // The letter the first word of a phrase must start with
+    pub last_letter: Option<char>, // The letter the last word of a phrase must end with
+    pub no_solutions_exit_code: i32, // The exit code when not every checksum was solved
+    pub smart_prune: bool, // Give up on a branch once a letter it still needs has no word left to supply it
+    pub max_recursion: Option<usize>, // The deepest the traversal may recurse, unlimited by default
+    pub max_depth: Option<usize>, // The most words a phrase may have, unlimited by default
+    pub jobs: Option<usize>, // How many root words are searched at once, the number of cores by default
+    pub target_parallelism: usize, // How many REPL targets are searched at once, sharing the jobs between them
+    pub output_prefix: String, // Put before every line the search prints, to tell apart the REPL targets searched at once
+    pub wildcards: u32, // Blank letters in the target that can stand in for any letter a word is short of
+    pub subset: bool, // A phrase is complete when no more words fit rather than when no letters are left
+    pub min_word_len: usize, // Words shorter than this are left out of the search
+    pub top_words: Option<usize>, // Only search this many words, the ones with the scarcest letters first
+    pub max_bucket_words: Option<usize>, // Only try this many of the words sharing a sorted anagram, the first in sorted order
+    pub shuffle_roots: bool, // Search the roots in a random order instead of longest first
+    pub seed: Option<u64>, // The seed for shuffle_roots, taken from the clock when not given
+    pub skip_roots: Vec<String>, // Sorted keys left out of the search, known to lead nowhere
+    pub skip_roots_file: Option<String>, // A file of more sorted keys to skip, one per line
+    pub min_phrase_len: usize, // Phrases with fewer letters than this are left out
+    pub top_phrases: Option<usize>, // How many of the best scoring phrases to keep and print
+    pub word_scores: Option<String>, // A file of "word score" lines the phrases are ranked by
+    pub output_format: OutputFormat, // How the solutions are printed at the end of the search
+}
+
+impl Default for AnagramOptions {
+    fn default() -> Self {
+        AnagramOptions {
+            target: None,
+            combined: None,
+            wordlist: None,
+            checksum_bin: Vec::new(),
+            uppercase_hash: false,
+            print_digests: false,
+            prefix_match: 0,
+            stream: false,
+            index: None,
+            write_index: None,
+            parallel_load: false,
+            compact_keys: false,
+            analyze_wordlist: false,
+            repl: false,
+            profile: false,
+            metrics_out: None,
+            dot: None,
+            show_target_key: false,
+            no_word_order: false,
+            count_only: false,
+            stream_solutions: false,
+            sort_by_time: false,
+            all_matches: false,
+            dump_excluded: None,
+            phrase_allow: Vec::new(),
+            phrase_deny: Vec::new(),
+            first_letter: None,
+            last_letter: None,
+            no_solutions_exit_code: EXIT_UNSOLVED,
+            smart_prune: false,
+            max_recursion: None,
+            max_depth: None,
+            jobs: None,
+            target_parallelism: 1,
+            output_prefix: String::new(),
+            wildcards: 0,
+            subset: false,
+            min_word_len: 0,
+            top_words: None,
+            max_bucket_words: None,
+            shuffle_roots: false,
+            skip_roots: Vec::new(),
+            skip_roots_file: None,
+            seed: None,
+            min_phrase_len: 0,
+            top_phrases: None,
+            word_scores: None,
+            output_format: OutputFormat::Text,
+        }
+    }
+}
+
+/// How the solutions are printed once the search is over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text, // "checksum : phrase, time to find" lines
+    Csv, // A phrase,digest,duration_ms header and a row per solution
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("Unknown output format {}, expected text or csv", s)),
+        }
+    }
+}
+
+/// The digest of a phrase, kept apart from the hashing library's own type so
+/// the rest of the code doesn't depend on which algorithm produced it.
+/// Displayed and parsed as lowercase hex.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Digest(Vec<u8>);
+
+impl From<md5::Digest> for Digest {
+    fn from(digest: md5::Digest) -> Self {
+        Digest(digest.0.to_vec())
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(&self.0))
+    }
+}
+
+impl Digest {
+    /// Whether the first hex_len hex characters of the two digests are the same.
+    fn prefix_matches(&self, other: &Digest, hex_len: usize) -> bool {
+        let bytes = hex_len / 2;
+        let odd = hex_len % 2 == 1;
+        if self.0.len() < bytes + odd as usize || other.0.len() < bytes + odd as usize {
+            return false;
+        }
+        // An odd length ends halfway through a byte, so only its high nibble is compared.
+        self.0[..bytes] == other.0[..bytes]
+            && (!odd || self.0[bytes] >> 4 == other.0[bytes] >> 4)
+    }
+}
+
+/// The digest of a phrase, computed the same way the search computes the digest
+/// of every phrase it compares against the checksums, which is MD5.
+///
+/// ```
+/// use any_anagram::digest_of;
+///
+/// assert_eq!(digest_of("").to_string(), "d41d8cd98f00b204e9800998ecf8427e");
+/// assert_eq!(digest_of("abc").to_string(), "900150983cd24fb0d6963f7d28e17f72");
+/// assert_eq!(digest_of("message digest").to_string(), "f96b697d7cb7938d525a2f31aaf161d0");
+/// ```
+pub fn digest_of(phrase: &str) -> Digest {
+    Digest::from(md5::compute(phrase))
+}
+
+// The bytes in an MD5 digest, anything else can't be one of the checksums.
+const DIGEST_LEN: usize = 16;
+
+impl FromStr for Digest {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s).map_err(|err| err.to_string())?;
+        if bytes.len() != DIGEST_LEN {
+            return Err(format!("Expected the {} bytes of an MD5 digest, got {}", DIGEST_LEN, bytes.len()));
+        }
+        Ok(Digest(bytes))
+    }
+}
+
+#[derive(Clone, Debug)]
+struct AnagramSearch {
+    anagram_chars_search: HashMap<char, u32>, // The sorted anagram chars to search for
+    options: AnagramOptions, // The command line options that affect the search
+    stop: Arc<AtomicBool>, // Set once the search is over so the tasks still running give up
+    solved: Arc<Mutex<HashSet<Digest>>>, // The checksums any task has solved so far
+    unsolved: HashSet<Digest>, // This task's own copy of the checksums left to compare against
+    letter_suppliers: HashMap<char, u32>, // How many sorted anagrams from the resume index on have each letter, with --smart-prune
+    wildcards_left: u32, // Wildcards not yet standing in for a letter
+    wildcard_letters: HashMap<char, u32>, // The letters the wildcards in use stand in for
+}
+
+#[derive(Clone, Debug)]
+struct AnagramSearchLookups {
+    md5_checksums: HashSet<Digest>, // Checksum to compare anagram phrases
+    anagrams_sorted_vec: Vec<String>, // Sorted anagram, to maintain order
+    anagrams_sorted_map: HashMap<String, Vec<String>>, // Sorted anagram -> Multiple Words
+    anagrams_sorted_chars: HashMap<String, HashMap<char, u32>>, // Sorted anagram -> # Characters
+    word_scores: HashMap<String, u64>, // Word -> Score, for ranking the top phrases
+}
+
+#[derive(Clone, Debug)]
+struct AnagramSolutionMetrics {
+    anagram_phrase_checksum: Digest,
+    anagram_phrase_time: std::time::Instant,
+    anagram_phrase_wildcards: String, // The letters the wildcards stood in for, sorted
+}
+
+/// How many of the dictionary's sorted anagrams survived the character filter.
+/// Printed before the search and written to --metrics-out as "filter".
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct FilterStats {
+    pub total: usize, // Sorted anagrams in the dictionary
+    pub excluded: usize, // Sorted anagrams needing characters the target doesn't have, or too short
+    pub remaining: usize, // Sorted anagrams left to search
+}
+
+/// Who doesn't like metrics? This data structure contains all of the interesting
+/// factoids that will be printed out at the end of the program's run.
+#[derive(Clone, Debug)]
+struct AnagramMetrics {
+    anagram_phrase_solution: HashMap<String, AnagramSolutionMetrics>, // The anagram phrase solutions
+    anagram_phrases_incomplete: u64, // How many incomplete phrases couldn't match the anagram characters
+    anagram_roots_exhausted: u64, // How many anagram root words have been exhaustively calculated
+    anagram_phrases_found: u64, // How many suitable phrases were found and calculated as MD5
+    anagram_phrases_counted: u64, // How many distinct phrases were counted with --count-only
+    anagram_phrase_max_depth: u32, // The largest number of suitable words had fit in a the anagram phrase
+    anagram_recursion_max_depth: u32, // The deepest the traversal recursed, complete phrase or not
+    anagram_branches_pruned: u64, // How many branches were abandoned for recursing past --max-recursion
+    anagram_branches_smart_pruned: u64, // How many branches --smart-prune cut short for a letter nothing left could supply
+    anagram_top_phrases: BinaryHeap<Reverse<ScoredPhrase>>, // The best scoring phrases, the worst of them on top to be replaced
+    anagram_profile: SearchProfile, // Time spent in each phase, only measured with --profile
+    anagram_dot_nodes: Vec<DotNode>, // The traversal's steps recorded for --dot, up to DOT_MAX_NODES of them
+    anagram_dot_truncated: bool, // Whether there were more steps than DOT_MAX_NODES
+    is_done: bool, // Signaling that the metrics for the root word is complete and can be tallied
+}
+
+/// The most steps of the traversal --dot records, for each root and for the
+/// whole search. Past a few thousand nodes the graph is too big to lay out or
+/// read anyway.
+const DOT_MAX_NODES: usize = 1_000;
+
+/// A step of the traversal recorded for --dot, trying one more sorted anagram.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct DotNode {
+    path: Vec<String>, // The sorted anagrams collected, the last one being the one tried
+    kind: DotNodeKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum DotNodeKind {
+    Fits, // The sorted anagram fit in the letters left and the search went on from it
+    DoesntFit, // The sorted anagram had letters that weren't left, pruning the branch
+    Complete, // The sorted anagram used up the letters left, completing a phrase
+}
+
+/// Where a root task's time went, measured with --profile. Reading the clock
+/// costs about as much as the letter arithmetic it surrounds, so the numbers
+/// are for comparing the phases rather than absolute.
+#[derive(Clone, Copy, Debug, Default)]
+struct SearchProfile {
+    traverse: Duration, // Everything the root tasks did
+    chars: Duration, // Subtracting and adding back the letters of sorted anagrams
+    permutate: Duration, // Ordering the words of complete phrases, hashing included
+    hash: Duration, // Computing the digests and comparing them to the checksums
+}
+
+/// A completed phrase ranked by the scores of its words, the higher the better.
+/// Ties go to the phrase with fewer words, then to the alphabetically first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ScoredPhrase {
+    score: u64, // The sum of the word scores, words without one score 0
+    words: usize, // How many words are in the phrase
+    phrase: String,
+}
+
+impl Ord for ScoredPhrase {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score.cmp(&other.score)
+            .then_with(|| other.words.cmp(&self.words))
+            .then_with(|| other.phrase.cmp(&self.phrase))
+    }
+}
+
+impl PartialOrd for ScoredPhrase {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Opens a file for reading, with a clear message if it can't be.
+fn open_file(filename: &str) -> Result<File, String> {
+    File::open(filename).map_err(|err| format!("Could not open {}: {}", filename, err))
+}
+
+// The message for a file that opened but then couldn't be read.
+fn read_error(filename: &str, err: std::io::Error) -> String {
+    format!("Could not read {}: {}", filename, err)
+}
+
+fn excluded_error(filename: &str, err: std::io::Error) -> String {
+    format!("Could not write the excluded words to {}: {}", filename, err)
+}
+
+// The file or URL the options read the wordlist from, to say which one failed.
+fn wordlist_name(options: &AnagramOptions) -> &str {
+    options.combined.as_deref().or(options.wordlist.as_deref()).unwrap_or("resources/wordlist")
+}
+
+// Load the wordlist the way the options ask for, all of it unless it's streamed.
+fn get_anagram_map_loaded(wordlist: Wordlist, options: &AnagramOptions) -> Result<HashMap<String, HashSet<String>>, String> {
+    let anagram_map = if options.parallel_load { get_anagram_map_parallel(wordlist, options.compact_keys) } else { get_anagram_map(wordlist, options.compact_keys) };
+    anagram_map.map_err(|err| read_error(wordlist_name(options), err))
+}
+
+/// Reads a digest stored as raw bytes rather than hex, failing if the file
+/// isn't exactly the size of an MD5 digest.
+fn get_checksum_bin(filename: &str) -> Result<Digest, String> {
+    let mut bytes: Vec<u8> = Vec::new();
+    open_file(filename)?.read_to_end(&mut bytes).map_err(|err| read_error(filename, err))?;
+    if bytes.len() != DIGEST_LEN {
+        return Err(format!("Checksum file {} is {} bytes, an MD5 digest is {} bytes", filename, bytes.len(), DIGEST_LEN));
+    }
+    Ok(Digest(bytes))
+}
+
+/// Reads the scores to rank phrases by, one "word score" per line. The score
+/// is the last thing on the line so a word may have spaces in it. Fails on a
+/// line without a score.
+fn get_word_scores(filename: &str) -> Result<HashMap<String, u64>, String> {
+    let mut word_scores: HashMap<String, u64> = HashMap::new();
+    for line in BufReader::new(open_file(filename)?).lines() {
+        let line = line.map_err(|err| read_error(filename, err))?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let score = line.rsplit_once(char::is_whitespace)
+            .and_then(|(word, score)| Some((word.trim(), score.parse::<u64>().ok()?)));
+        match score {
+            Some((word, score)) => { word_scores.insert(word.to_string(), score); }
+            None => return Err(format!("Expected \"word score\" in {}: {}", filename, line)),
+        }
+    }
+    Ok(word_scores)
+}
+
+// Read the sorted keys to skip, one per line, sorting each one's letters so
+// a key copied from a word still matches.
+fn get_skip_roots(filename: &str) -> Result<Vec<String>, String> {
+    let mut skipped: Vec<String> = Vec::new();
+    for line in BufReader::new(open_file(filename)?).lines() {
+        let line = line.map_err(|err| read_error(filename, err))?;
+        if !line.trim().is_empty() {
+            skipped.push(get_anagram_sorted(line.trim()));
+        }
+    }
+    Ok(skipped)
+}
+
+/// Retrieves the anagram phrase from resources/anagram
+/// and sorts it as an anagram phrase while omitting the spaces.
+/// Fails if the file doesn't hold exactly one phrase, see read_anagram.
+fn get_anagram() -> Result<(String, String), String> {
+    let filename = "resources/anagram";
+    read_anagram(BufReader::new(open_file(filename)?)).map_err(|err| format!("{}: {}", filename, err))
+}
+
+/// Reads an anagram file, which holds the phrase on a single line. Blank lines
+/// around it are ignored, but a second line with anything on it is an error
+/// rather than being dropped or joined on, since either guess could be wrong.
+fn read_anagram(f: impl BufRead) -> Result<(String, String), String> {
+    let mut lines = Vec::new();
+    for line in f.lines() {
+        let line = line.map_err(|err| err.to_string())?;
+        if !line.trim().is_empty() {
+            lines.push(line);
+        }
+    }
+
+    match lines.as_slice() {
+        [phrase] => Ok(parse_anagram(phrase)),
+        [] => Err("Expected the anagram phrase but the file is empty".to_string()),
+        _ => Err(format!("Expected the anagram phrase on one line but found {} lines, put the whole phrase on one line", lines.len())),
+    }
+}
+
+/// Sorts an anagram phrase while omitting the spaces, after trimming
+/// the surrounding whitespace such as the line ending.
+fn parse_anagram(phrase: &str) -> (String, String) {
+    let phrase = phrase.trim();
+    (get_anagram_sorted(phrase), phrase.to_string())
+}
+
+/// A wordlist being read, one word or phrase to a line.
+type Wordlist = Box<dyn BufRead>;
+
+/// Opens the wordlist given, or the one at resources/wordlist.
+/// An http(s):// URL is downloaded as it's read, which needs the net feature.
+fn open_wordlist(filename: Option<&str>) -> Result<Wordlist, String> {
+    let filename = filename.unwrap_or("resources/wordlist");
+    if filename.starts_with("http://") || filename.starts_with("https://") {
+        return open_url(filename);
+    }
+    Ok(Box::new(BufReader::new(open_file(filename)?)))
+}
+
+/// Requests a URL and returns its body to read as it arrives, failing with
+/// the reason if the request fails or the status isn't a success.
+#[cfg(feature = "net")]
+fn open_url(url: &str) -> Result<Wordlist, String> {
+    match ureq::get(url).call() {
+        Ok(response) => Ok(Box::new(BufReader::new(response.into_body().into_reader()))),
+        Err(err) => Err(format!("Could not download {}: {}", url, err)),
+    }
+}
+
+#[cfg(not(feature = "net"))]
+fn open_url(url: &str) -> Result<Wordlist, String> {
+    Err(format!("Could not download {}: built without the net feature, build with --features net", url))
+}
+
+/// Reads a combined puzzle file where the first line is the anagram phrase
+/// and every line after it is the wordlist. Returns the anagram the same way
+/// get_anagram does along with the rest of the file to read the wordlist from.
+/// Fails if there isn't at least a phrase and one word.
+fn get_combined(filename: &str) -> Result<((String, String), Wordlist), String> {
+    let mut f = BufReader::new(open_file(filename)?);
+    let mut buffer = String::new();
+    f.read_line(&mut buffer).map_err(|err| read_error(filename, err))?;
+
+    let anagram = parse_anagram(&buffer);
+    let has_wordlist = !f.fill_buf().map_err(|err| read_error(filename, err))?.is_empty();
+    if anagram.0.is_empty() || !has_wordlist {
+        return Err(format!("Combined file {} needs the anagram phrase on the first line and the wordlist after it", filename));
+    }
+
+    Ok((anagram, Box::new(f)))
+}
+
+// Sort the characters of a dictionary entry into its anagram key.
+// Spaces are left out so a multi-word entry like "new york" is keyed by its
+// letters alone, the same way the spaces are left out of the anagram.
+fn get_anagram_sorted(word: &str) -> String {
+    let mut chars: Vec<char> = word.chars().filter(|&x| x != ' ').collect();
+    chars.sort();
+    chars.iter().collect()
+}
+
+// Encode a dictionary entry's letters as each distinct letter in sorted order
+// followed by how many times it appears when that's more than once.
+// EG: banana -> a3bn2
+// It's never longer than the sorted key and its letter counts can be read
+// straight off it. A digit in the word would make the counts ambiguous, so
+// there's no compact key for one.
+fn get_anagram_compact(word: &str) -> Option<String> {
+    if word.chars().any(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let anagram_sorted = get_anagram_sorted(word);
+    let mut anagram_compact = String::with_capacity(anagram_sorted.len());
+    let mut chars = anagram_sorted.chars().peekable();
+    while let Some(c) = chars.next() {
+        let mut count: u32 = 1;
+        while chars.next_if_eq(&c).is_some() {
+            count += 1;
+        }
+        anagram_compact.push(c);
+        if count > 1 {
+            anagram_compact.push_str(&count.to_string());
+        }
+    }
+    Some(anagram_compact)
+}
+
+// Count the letters of a key made by get_anagram_compact.
+fn count_compact_chars(anagram_compact: &str) -> HashMap<char, u32> {
+    let mut char_count: HashMap<char, u32> = HashMap::new();
+    let mut letter: Option<char> = None;
+    let mut count: u32 = 0;
+    for c in anagram_compact.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            count = count * 10 + digit;
+            continue;
+        }
+        if let Some(letter) = letter {
+            *char_count.entry(letter).or_insert(0) += count.max(1);
+        }
+        letter = Some(c);
+        count = 0;
+    }
+    if let Some(letter) = letter {
+        *char_count.entry(letter).or_insert(0) += count.max(1);
+    }
+    char_count
+}
+
+// Turn a key made by get_anagram_compact back into the sorted key.
+fn expand_compact_key(anagram_compact: &str) -> String {
+    let mut char_count: Vec<(char, u32)> = count_compact_chars(anagram_compact).into_iter().collect();
+    char_count.sort();
+    char_count.iter().flat_map(|(c, count)| std::iter::repeat_n(*c, *count as usize)).collect()
+}
+
+// The key a loader files a dictionary entry under, None if it has to be left out.
+fn get_anagram_key(word: &str, compact_keys: bool) -> Option<String> {
+    if compact_keys { get_anagram_compact(word) } else { Some(get_anagram_sorted(word)) }
+}
+
+// Count the letters of a key made by get_anagram_key.
+fn count_key_chars(anagram_key: &str, compact_keys: bool) -> HashMap<char, u32> {
+    if compact_keys { count_compact_chars(anagram_key) } else { count_chars(anagram_key) }
+}
+
+// Warn about the words a loader left out for having no key.
+fn warn_unkeyed_words(unkeyed: usize) {
+    if unkeyed > 0 {
+        eprintln!("Warning: left out {} words with digits, compact keys can't encode them", unkeyed);
+    }
+}
+
+// Get the map of sorted words to words.
+// EG:
+// abcer <-- Key
+// --brace <-- Value
+// --crabe <-- Value
+// A multi-word entry keeps its spaces in the value, so "new york" is used as
+// a single word that happens to print as two.
+// With compact_keys the keys are made by get_anagram_compact instead.
+// Fails if a line can't be read, EG: it isn't UTF-8.
+fn get_anagram_map(f: Box<dyn BufRead>, compact_keys: bool) -> std::io::Result<HashMap<String, HashSet<String>>> {
+    let mut anagrams: HashMap<String, HashSet<String>> = HashMap::new();
+    let mut unkeyed: usize = 0;
+
+    for line in f.lines() {
+        let line = line?;
+        let value : String = String::from(&line);
+        let Some(anagram_sorted) = get_anagram_key(&value, compact_keys) else {
+            unkeyed += 1;
+            continue;
+        };
+        
+        anagrams.entry(anagram_sorted).and_modify(|hs| {hs.insert(value.clone());}).or_insert(HashSet::from([value]));
+    }
+    warn_unkeyed_words(unkeyed);
+    Ok(anagrams)
+}
+
+// Get the map of sorted words to words, like get_anagram_map, but sort the
+// words on every core. Each thread builds its own partial map and the partial
+// maps are merged at the end. Reading every line up front costs memory that
+// the sequential load doesn't, so it only pays off for large wordlists.
+fn get_anagram_map_parallel(f: Box<dyn BufRead>, compact_keys: bool) -> std::io::Result<HashMap<String, HashSet<String>>> {
+    let lines: Vec<String> = f.lines().collect::<std::io::Result<_>>()?;
+    if compact_keys {
+        warn_unkeyed_words(lines.iter().filter(|line| get_anagram_compact(line).is_none()).count());
+    }
+
+    Ok(lines.into_par_iter()
+        .fold(HashMap::new, |mut anagrams: HashMap<String, HashSet<String>>, value| {
+            if let Some(anagram_sorted) = get_anagram_key(&value, compact_keys) {
+                anagrams.entry(anagram_sorted).or_default().insert(value);
+            }
+            anagrams
+        })
+        .reduce(HashMap::new, |mut anagrams, partial| {
+            for (anagram_sorted, words) in partial {
+                anagrams.entry(anagram_sorted).or_default().extend(words);
+            }
+            anagrams
+        }))
+}
+
+// Get the map of sorted words to words, like get_anagram_map, but only keep
+// the words that fit in the anagram characters. Unusable words are dropped as
+// the file is read so the whole dictionary is never held in memory, which is
+// what makes dictionaries larger than RAM workable. The number of dropped
+// words is returned alongside the map, and they're written to excluded_out if given.
+// Fails if a line of the wordlist can't be read or excluded_out can't be written to.
+fn get_anagram_map_streamed(
+    f: Box<dyn BufRead>,
+    wordlist_name: &str,
+    anagram_chars: &HashMap<char, u32>,
+    wildcards: u32,
+    mut excluded_out: Option<(&mut dyn Write, &str)>) -> Result<(HashMap<String, HashSet<String>>, u64), String> {
+    let mut anagrams: HashMap<String, HashSet<String>> = HashMap::new();
+    let mut skipped: u64 = 0;
+
+    for line in f.lines() {
+        let value = line.map_err(|err| read_error(wordlist_name, err))?;
+        let anagram_sorted: String = get_anagram_sorted(&value);
+        let value_chars = count_chars(&anagram_sorted);
+        if !contains_chars_wild(anagram_chars, &value_chars, wildcards) {
+            if let Some((out, path)) = excluded_out.as_mut() {
+                write_excluded_word(out, &value, anagram_chars, &value_chars).map_err(|err| excluded_error(path, err))?;
+            }
+            skipped += 1;
+            continue;
+        }
+
+        anagrams.entry(anagram_sorted).or_default().insert(value);
+    }
+    Ok((anagrams, skipped))
+}
+
+/// Writes a wordlist loaded with load_anagram_map as an index to search with
+/// --index. Each line is a sorted anagram followed by its words, separated by
+/// tabs, and the lines are in the order the search goes through them.
+pub fn write_index(anagram_map: &HashMap<String, HashSet<String>>, path: &str) -> Result<(), String> {
+    let mut anagrams_sorted: Vec<&String> = anagram_map.keys().collect();
+    anagrams_sorted.sort_by(|a, b| search_order(a, b));
+
+    let written = File::create(path).and_then(|f| {
+        let mut out = BufWriter::new(f);
+        for anagram_sorted in anagrams_sorted {
+            let mut words: Vec<&String> = anagram_map[anagram_sorted].iter().collect();
+            words.sort();
+            write!(out, "{}", anagram_sorted)?;
+            for word in words {
+                write!(out, "\t{}", word)?;
+            }
+            writeln!(out)?;
+        }
+        out.flush()
+    });
+    written.map_err(|err| format!("Could not write the index to {}: {}", path, err))
+}
+
+/// The roots of an index that fit in the target, found by reading the index
+/// once before searching. Only the roots' letters are held for the whole search,
+/// the words of the roots that can follow a root are read from their lines as
+/// the root is searched.
+#[derive(Clone, Debug)]
+struct IndexRoots {
+    path: String, // The index file
+    roots: Vec<String>, // The sorted anagrams that fit in the target, in the index's order
+    offsets: HashMap<String, u64>, // Root -> Where its line starts in the index
+    lines: usize, // Sorted anagrams in the whole index
+}
+
+// Split an index line into its sorted anagram and words.
+fn parse_index_line(line: &str) -> (&str, std::str::Split<'_, char>) {
+    let mut fields = line.trim_end_matches(['\n', '\r']).split('\t');
+    (fields.next().unwrap_or_default(), fields)
+}
+
+// Read the roots of the index that fit in the target. Fails if the index isn't
+// in the order write_index puts it in, since a root is only combined with the
+// sorted anagrams after it.
+fn read_index_roots(path: &str, anagram_chars: &HashMap<char, u32>, min_word_len: usize, wildcards: u32) -> Result<IndexRoots, String> {
+    let mut f = BufReader::new(open_file(path)?);
+    let mut index_roots = IndexRoots { path: path.to_string(), roots: Vec::new(), offsets: HashMap::new(), lines: 0 };
+    let mut previous: Option<String> = None;
+    let mut offset: u64 = 0;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = f.read_line(&mut line).map_err(|err| read_error(path, err))?;
+        if read == 0 {
+            break;
+        }
+
+        let (anagram_sorted, _) = parse_index_line(&line);
+        let anagram_sorted = anagram_sorted.to_string();
+        if previous.as_ref().is_some_and(|previous| search_order(previous, &anagram_sorted) != Ordering::Less) {
+            return Err(format!("Index {} isn't in search order at line {}: {}, write it again with --write-index", path, index_roots.lines + 1, anagram_sorted));
+        }
+
+        if contains_chars_wild(anagram_chars, &count_chars(&anagram_sorted), wildcards) && anagram_sorted.chars().count() >= min_word_len {
+            index_roots.offsets.insert(anagram_sorted.clone(), offset);
+            index_roots.roots.push(anagram_sorted.clone());
+        }
+        index_roots.lines += 1;
+        offset += read as u64;
+        previous = Some(anagram_sorted);
+    }
+    Ok(index_roots)
+}
+
+impl IndexRoots {
+    // Read the lookups a root task searches from the index: the root and the
+    // roots after it that fit in the letters it left. Only a root can follow a
+    // root, so the other lines are never read, and the roots' letters are
+    // already counted in the lookups of the roots.
+    // With --subset the roots before it that fit go first, only their letters
+    // are needed to tell a phrase is complete. Returns the lookups and the
+    // root's index in them to resume from.
+    fn root_lookups(&self, root_index: usize, anagram_search: &AnagramSearch, roots_lookups: &AnagramSearchLookups) -> (AnagramSearchLookups, usize) {
+        let mut f = BufReader::new(open_file(&self.path).expect("Could not open the index"));
+
+        let mut anagram_search_lookups = AnagramSearchLookups {
+            md5_checksums: roots_lookups.md5_checksums.clone(),
+            anagrams_sorted_vec: Vec::new(),
+            anagrams_sorted_map: HashMap::new(),
+            anagrams_sorted_chars: HashMap::new(),
+            word_scores: roots_lookups.word_scores.clone(),
+        };
+        if anagram_search.options.subset {
+            for anagram_sorted in &roots_lookups.anagrams_sorted_vec[..root_index] {
+                let anagram_sorted_chars = &roots_lookups.anagrams_sorted_chars[anagram_sorted];
+                if contains_chars_wild(&anagram_search.anagram_chars_search, anagram_sorted_chars, anagram_search.wildcards_left) {
+                    anagram_search_lookups.anagrams_sorted_vec.push(anagram_sorted.clone());
+                    anagram_search_lookups.anagrams_sorted_chars.insert(anagram_sorted.clone(), anagram_sorted_chars.clone());
+                }
+            }
+        }
+        let resume_index = anagram_search_lookups.anagrams_sorted_vec.len();
+
+        let mut line = String::new();
+        for (anagram_sorted_index, anagram_sorted) in roots_lookups.anagrams_sorted_vec.iter().enumerate().skip(root_index) {
+            let anagram_sorted_chars = &roots_lookups.anagrams_sorted_chars[anagram_sorted];
+            // The root's letters are already taken out, so it's kept whether it fits again or not.
+            if anagram_sorted_index != root_index
+                && !contains_chars_wild(&anagram_search.anagram_chars_search, anagram_sorted_chars, anagram_search.wildcards_left) {
+                continue;
+            }
+
+            f.seek(SeekFrom::Start(self.offsets[anagram_sorted])).expect("Could not read the index");
+            line.clear();
+            f.read_line(&mut line).expect("Could not read the index");
+            let (_, words) = parse_index_line(&line);
+            anagram_search_lookups.anagrams_sorted_vec.push(anagram_sorted.clone());
+            anagram_search_lookups.anagrams_sorted_map.insert(anagram_sorted.clone(), words.map(str::to_string).collect());
+            anagram_search_lookups.anagrams_sorted_chars.insert(anagram_sorted.clone(), anagram_sorted_chars.clone());
+        }
+        if let Some(max_bucket_words) = anagram_search.options.max_bucket_words {
+            limit_bucket_words(&mut anagram_search_lookups, max_bucket_words);
+        }
+        (anagram_search_lookups, resume_index)
+    }
+}
+
+// Write a word the character filter excluded along with the characters it has
+// more of than the anagram, which answers why a word was never considered.
+// EG, for the anagram "tac dog":
+// zebra b e r z
+fn write_excluded_word(out: &mut dyn Write, word: &str, anagram_chars: &HashMap<char, u32>, word_chars: &HashMap<char, u32>) -> std::io::Result<()> {
+    let mut offending: Vec<char> = word_chars.iter()
+        .filter(|(word_char, word_count)| *word_count > anagram_chars.get(word_char).unwrap_or(&0))
+        .map(|(word_char, _)| *word_char)
+        .collect();
+    offending.sort();
+
+    let offending: Vec<String> = offending.iter().map(|c| c.to_string()).collect();
+    writeln!(out, "{} {}", word, offending.join(" "))
+}
+
+// Determine if the character count is within the limit of the given character sequence.
+// EG:
+// If the letter Y has 6 instances then the comparison of Y having 5 instances will return true.
+// If the ltter X has 2 instances then the comparison of X having 3 instances will return false.
+fn contains_chars(required: &HashMap<char, u32>, compare: &HashMap<char, u32>) -> bool {
+    if compare.len() > required.len() {
+        return false;
+    }
+
+    for (compare_char, compare_count) in compare {
+        let required_count = required.get(compare_char);
+        if required_count.is_none() || compare_count > required_count.unwrap() {
+            return false;
+        }
+    }
+    true
+}
+
+// Count the characters from a string sequence
+fn count_chars(char_sequence: &str) -> HashMap<char, u32> {
+    let mut char_hash : HashMap<char, u32> = HashMap::new();
+
+    for char in char_sequence.chars() {
+        *char_hash.entry(char).or_insert(0) += 1;
+    }
+
+    char_hash
+}
+
+// Add the character count to another character count
+fn add_chars(source: &mut HashMap<char, u32>, add: &HashMap<char, u32>) {
+    for (char_key, char_count) in add.iter() {
+        source.entry(*char_key).and_modify(|counter| *counter += *char_count).or_insert(*char_count);
+    }
+}
+
+// Subtract the character count from another character count.
+// This will return false if the subtracted character count is greater
+// than the source; character counts can't be negative.
+fn subtract_chars(source: &mut HashMap<char, u32>, subtract: &HashMap<char, u32>) -> bool {
+    if !contains_chars(source, subtract) {
+        return false;
+    }
+
+    for (char_key, char_count) in subtract.iter() {
+        source.entry(*char_key).and_modify(|counter| *counter -= *char_count);
+
+        let char_value = source.get(char_key);
+        if char_value == Some(&0) {
+            source.remove(char_key);
+        }
+    }
+
+    true
+}
+
+// Determine if the character count fits like contains_chars, when up to wildcards
+// of the letters compare has more of than required can be made up by wildcards.
+fn contains_chars_wild(required: &HashMap<char, u32>, compare: &HashMap<char, u32>, wildcards: u32) -> bool {
+    let mut short: u32 = 0;
+    for (compare_char, compare_count) in compare {
+        short += compare_count.saturating_sub(*required.get(compare_char).unwrap_or(&0));
+        if short > wildcards {
+            return false;
+        }
+    }
+    true
+}
+
+// Subtract like subtract_chars, but make up the letters the source is short of
+// from the wildcards left. The letters they stand in for are added to
+// wildcard_letters so add_chars_wild can give the wildcards back.
+// A wildcard only stands in for a letter once the source has none of it left,
+// which keeps add_chars_wild the exact reverse of this.
+fn subtract_chars_wild(
+    source: &mut HashMap<char, u32>,
+    subtract: &HashMap<char, u32>,
+    wildcards_left: &mut u32,
+    wildcard_letters: &mut HashMap<char, u32>) -> bool {
+
+    if !contains_chars_wild(source, subtract, *wildcards_left) {
+        return false;
+    }
+
+    for (char_key, char_count) in subtract.iter() {
+        let char_left = *source.get(char_key).unwrap_or(&0);
+        if char_left > *char_count {
+            *source.get_mut(char_key).unwrap() -= *char_count;
+            continue;
+        }
+
+        source.remove(char_key);
+        let short = *char_count - char_left;
+        if short > 0 {
+            *wildcards_left -= short;
+            *wildcard_letters.entry(*char_key).or_insert(0) += short;
+        }
+    }
+
+    true
+}
+
+// Add the character count back after subtract_chars_wild, giving back the
+// wildcards that stood in for its letters before the letters themselves.
+fn add_chars_wild(
+    source: &mut HashMap<char, u32>,
+    add: &HashMap<char, u32>,
+    wildcards_left: &mut u32,
+    wildcard_letters: &mut HashMap<char, u32>) {
+
+    for (char_key, char_count) in add.iter() {
+        let standing_in = *wildcard_letters.get(char_key).unwrap_or(&0);
+        let given_back = standing_in.min(*char_count);
+        if given_back > 0 {
+            *wildcards_left += given_back;
+            if given_back == standing_in {
+                wildcard_letters.remove(char_key);
+            } else {
+                *wildcard_letters.get_mut(char_key).unwrap() -= given_back;
+            }
+        }
+        if *char_count > given_back {
+            *source.entry(*char_key).or_insert(0) += *char_count - given_back;
+        }
+    }
+}
+
+// Subtract a sorted anagram's letters from those the search has left, with
+// the search's wildcards when it has any.
+fn subtract_search_chars(anagram_search: &mut AnagramSearch, subtract: &HashMap<char, u32>) -> bool {
+    if anagram_search.options.wildcards == 0 {
+        return subtract_chars(&mut anagram_search.anagram_chars_search, subtract);
+    }
+    subtract_chars_wild(
+        &mut anagram_search.anagram_chars_search,
+        subtract,
+        &mut anagram_search.wildcards_left,
+        &mut anagram_search.wildcard_letters)
+}
+
+// Add back the letters subtract_search_chars took.
+fn add_search_chars(anagram_search: &mut AnagramSearch, add: &HashMap<char, u32>) {
+    if anagram_search.options.wildcards == 0 {
+        add_chars(&mut anagram_search.anagram_chars_search, add);
+        return;
+    }
+    add_chars_wild(
+        &mut anagram_search.anagram_chars_search,
+        add,
+        &mut anagram_search.wildcards_left,
+        &mut anagram_search.wildcard_letters);
+}
+
+// Filter the anagrams that have more characters than what's provided, or
+// fewer letters than min_word_len.
+// Returns the remaining anagrams along with the numbers for reporting.
+fn filter_anagram_chars(
+    anagram_chars: &HashMap<char, u32>,
+    anagram_chars_list: &HashMap<String, HashMap<char, u32>>,
+    min_word_len: usize,
+    wildcards: u32) -> (HashMap<String, HashMap<char, u32>>, FilterStats) {
+
+    let mut filter_stats = FilterStats { total: anagram_chars_list.len(), ..FilterStats::default() };
+    let mut anagrams_remaining_char_list: HashMap<String, HashMap<char, u32>> = HashMap::new();
+    for (anagram_sorted, anagram_counted_chars) in anagram_chars_list.iter() {
+        if !contains_chars_wild(anagram_chars, anagram_counted_chars, wildcards) || anagram_sorted.chars().count() < min_word_len {
+            filter_stats.excluded += 1;
+        } else {
+            anagrams_remaining_char_list.insert(anagram_sorted.clone(), anagram_counted_chars.clone());
+        }
+    }
+    filter_stats.remaining = anagrams_remaining_char_list.len();
+
+    (anagrams_remaining_char_list, filter_stats)
+}
+
+// The order the search goes through the sorted anagrams in.
+// The sort priority is length first then by alphabetical
+// This has a functional impact (optimization)
+// on eliminating as many negatives from
+// the beginning as possible.
+fn search_order(a: &String, b: &String) -> Ordering {
+    b.len().cmp(&a.len()).then_with(|| a.cmp(b))
+}
+
+// Build the lookups the search needs from the map of sorted words to words,
+// keeping only the anagrams that fit in the anagram characters.
+fn build_anagram_search_lookups(
+    anagram_map: &HashMap<String, HashSet<String>>,
+    anagram_chars: &HashMap<char, u32>,
+    md5_checksums: HashSet<Digest>,
+    min_word_len: usize,
+    wildcards: u32) -> (AnagramSearchLookups, FilterStats) {
+
+    // Get the anagram
+    let mut anagram_chars_list : HashMap<String, HashMap<char, u32>> = HashMap::new();
+    for anagram_sorted in anagram_map.keys() {
+        anagram_chars_list.insert(anagram_sorted.clone(), count_chars(anagram_sorted));
+    }
+
+    // Filter the anagrams that have more characters than what's provided.
+    let (anagrams_remaining_char_list, filter_stats) = filter_anagram_chars(anagram_chars, &anagram_chars_list, min_word_len, wildcards);
+
+    // Create a vector from the hashmap of anagrams. Vectors are ordered, hashmaps are random.
+    // An ordered set is necessary for optimizing the removal of repeated negative searches.
+    let mut anagram_map_vec: HashMap<String, Vec<String>> = HashMap::new();
+    let mut anagram_sorted_list_vec: Vec<String> = Vec::new();
+    for (anagram_sorted, _) in anagrams_remaining_char_list.iter() {
+        let anagram_words: &HashSet<String> = anagram_map.get(anagram_sorted).unwrap();
+        anagram_map_vec.insert(anagram_sorted.clone(), Vec::from_iter(anagram_words.clone()));
+        anagram_sorted_list_vec.push(anagram_sorted.clone());
+    }
+
+    anagram_sorted_list_vec.sort_by(search_order);
+
+    // Every root must only be processed once, otherwise its phrases are found
+    // twice and the metrics are inflated. The sort above keeps duplicates next
+    // to each other so dedup is enough to remove them.
+    let sorted_count = anagram_sorted_list_vec.len();
+    anagram_sorted_list_vec.dedup();
+    if anagram_sorted_list_vec.len() != sorted_count {
+        eprintln!("Warning: removed {} duplicate anagram roots", sorted_count - anagram_sorted_list_vec.len());
+    }
+
+    // Package it all into a neat struct
+    let anagram_search_lookups: AnagramSearchLookups = AnagramSearchLookups {
+        anagrams_sorted_chars: anagrams_remaining_char_list,
+        anagrams_sorted_vec: anagram_sorted_list_vec,
+        anagrams_sorted_map: anagram_map_vec,
+        md5_checksums,
+        word_scores: HashMap::new(),
+    };
+
+    (anagram_search_lookups, filter_stats)
+}
+
+// Print the solutions a task reported that haven't been seen before.
+// Tasks report every solution they have found so far, not only the newest one.
+fn print_new_solutions(prefix: &str, metrics: &AnagramMetrics, metrics_received: &AnagramMetrics, start_time: Instant) {
+    for (phrase, solution_metrics) in metrics_received.anagram_phrase_solution.iter() {
+        if !metrics.anagram_phrase_solution.contains_key(phrase) {
+            say!(prefix, "Found solution: {} : {}, time to find: {:?}{}",
+                solution_metrics.anagram_phrase_checksum,
+                phrase,
+                solution_metrics.anagram_phrase_time.duration_since(start_time),
+                wildcards_note(solution_metrics));
+        }
+    }
+}
+
+// The letters a solution's wildcards stood in for, to follow its time to find.
+fn wildcards_note(solution_metrics: &AnagramSolutionMetrics) -> String {
+    if solution_metrics.anagram_phrase_wildcards.is_empty() {
+        String::new()
+    } else {
+        format!(", wildcards: {}", solution_metrics.anagram_phrase_wildcards)
+    }
+}
+
+// The throughput of the hot path, the single most useful number for benchmarking it.
+fn phrases_per_second(phrases: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        return 0.0;
+    }
+    phrases as f64 / elapsed.as_secs_f64()
+}
+
+// Used for metrics & reporting
+fn add_metrics(total_metric: &mut AnagramMetrics, add_metric: AnagramMetrics, options: &AnagramOptions) {
+    // A task reports each solution as it's found and again with the rest of its
+    // metrics once it's done, the map keeps the repeat from counting twice.
+    total_metric.anagram_phrase_solution.extend(add_metric.anagram_phrase_solution);
+
+    // The counts in a message sent before the task is done are only partial,
+    // they're all sent again in the final one.
+    if !add_metric.is_done {
+        return;
+    }
+
+    total_metric.anagram_roots_exhausted += 1;
+    total_metric.anagram_profile.traverse += add_metric.anagram_profile.traverse;
+    total_metric.anagram_profile.chars += add_metric.anagram_profile.chars;
+    total_metric.anagram_profile.permutate += add_metric.anagram_profile.permutate;
+    total_metric.anagram_profile.hash += add_metric.anagram_profile.hash;
+    total_metric.anagram_phrases_incomplete += add_metric.anagram_phrases_incomplete;
+    total_metric.anagram_phrases_found += add_metric.anagram_phrases_found;
+    total_metric.anagram_phrases_counted += add_metric.anagram_phrases_counted;
+    total_metric.anagram_branches_pruned += add_metric.anagram_branches_pruned;
+    total_metric.anagram_branches_smart_pruned += add_metric.anagram_branches_smart_pruned;
+    if total_metric.anagram_phrase_max_depth < add_metric.anagram_phrase_max_depth {
+        total_metric.anagram_phrase_max_depth = add_metric.anagram_phrase_max_depth;
+    }
+    if total_metric.anagram_recursion_max_depth < add_metric.anagram_recursion_max_depth {
+        total_metric.anagram_recursion_max_depth = add_metric.anagram_recursion_max_depth;
+    }
+    // Sorted, a node's parent comes before it, so truncating keeps the graph connected.
+    total_metric.anagram_dot_nodes.extend(add_metric.anagram_dot_nodes);
+    total_metric.anagram_dot_truncated |= add_metric.anagram_dot_truncated;
+    if total_metric.anagram_dot_nodes.len() > DOT_MAX_NODES {
+        total_metric.anagram_dot_nodes.sort();
+        total_metric.anagram_dot_nodes.truncate(DOT_MAX_NODES);
+        total_metric.anagram_dot_truncated = true;
+    }
+    if let Some(top_phrases) = options.top_phrases {
+        for Reverse(scored_phrase) in add_metric.anagram_top_phrases {
+            push_top_phrase(&mut total_metric.anagram_top_phrases, scored_phrase, top_phrases);
+        }
+    }
+}
+
+// Keep a phrase if it's among the best top_phrases, the heap keeps the worst
+// of them on top so it's the one dropped when a better phrase comes along.
+// Swapping the words of a repeated sorted anagram builds the same phrase again,
+// it's only kept once.
+fn push_top_phrase(anagram_top_phrases: &mut BinaryHeap<Reverse<ScoredPhrase>>, scored_phrase: ScoredPhrase, top_phrases: usize) {
+    if anagram_top_phrases.iter().any(|Reverse(top_phrase)| top_phrase.phrase == scored_phrase.phrase) {
+        return;
+    }
+    anagram_top_phrases.push(Reverse(scored_phrase));
+    if anagram_top_phrases.len() > top_phrases {
+        anagram_top_phrases.pop();
+    }
+}
+
+// The score of a phrase is the sum of its words' scores.
+fn score_phrase(words: &[&String], word_scores: &HashMap<String, u64>) -> u64 {
+    words.iter().map(|word| word_scores.get(*word).copied().unwrap_or(0)).sum()
+}
+
+// Cut the vocabulary down to the top_words words that use the target's scarcest
+// letters, the ones few words can supply and so the most likely to be the
+// bottleneck. A letter weighs how many of it the target needs over how many of
+// the remaining sorted anagrams have it. This is a heuristic, the words left
+// out may well have been part of a solution.
+// Returns how many sorted anagrams were kept.
+fn limit_top_words(anagram_search_lookups: &mut AnagramSearchLookups, anagram_chars: &HashMap<char, u32>, top_words: usize) -> usize {
+    let mut letter_supply: HashMap<char, u32> = HashMap::new();
+    for anagram_sorted_chars in anagram_search_lookups.anagrams_sorted_chars.values() {
+        for c in anagram_sorted_chars.keys() {
+            *letter_supply.entry(*c).or_insert(0) += 1;
+        }
+    }
+
+    let score = |anagram_sorted: &String| -> f64 {
+        anagram_search_lookups.anagrams_sorted_chars[anagram_sorted].iter()
+            .map(|(c, count)| *count as f64 * *anagram_chars.get(c).unwrap_or(&0) as f64 / letter_supply[c] as f64)
+            .sum()
+    };
+    let mut ranked: Vec<(f64, &String)> = anagram_search_lookups.anagrams_sorted_vec.iter()
+        .map(|anagram_sorted| (score(anagram_sorted), anagram_sorted))
+        .collect();
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+
+    // Whole sorted anagrams are kept until the last one, which may only keep some of its words.
+    let mut remaining = top_words;
+    let mut kept: HashSet<String> = HashSet::new();
+    for (_, anagram_sorted) in ranked {
+        if remaining == 0 {
+            break;
+        }
+        let words = anagram_search_lookups.anagrams_sorted_map.get_mut(anagram_sorted).unwrap();
+        words.sort();
+        words.truncate(remaining);
+        remaining -= words.len();
+        kept.insert(anagram_sorted.clone());
+    }
+
+    anagram_search_lookups.anagrams_sorted_vec.retain(|anagram_sorted| kept.contains(anagram_sorted));
+    anagram_search_lookups.anagrams_sorted_map.retain(|anagram_sorted, _| kept.contains(anagram_sorted));
+    anagram_search_lookups.anagrams_sorted_chars.retain(|anagram_sorted, _| kept.contains(anagram_sorted));
+    kept.len()
+}
+
+// Keep only the first words in sorted order of every sorted anagram with more
+// than max_bucket_words of them. Like limit_top_words this is an approximation,
+// a solution using one of the words left out won't be found.
+// Returns how many words were left out.
+fn limit_bucket_words(anagram_search_lookups: &mut AnagramSearchLookups, max_bucket_words: usize) -> usize {
+    let mut skipped: usize = 0;
+    for words in anagram_search_lookups.anagrams_sorted_map.values_mut() {
+        if words.len() > max_bucket_words {
+            words.sort();
+            skipped += words.len() - max_bucket_words;
+            words.truncate(max_bucket_words);
+        }
+    }
+    skipped
+}
+
+// Leave the sorted keys out of the search entirely, both as roots and as the
+// words that follow them.
+// Returns how many of the keys were in the search.
+fn skip_roots(anagram_search_lookups: &mut AnagramSearchLookups, skipped: &HashSet<String>) -> usize {
+    let before = anagram_search_lookups.anagrams_sorted_vec.len();
+    anagram_search_lookups.anagrams_sorted_vec.retain(|anagram_sorted| !skipped.contains(anagram_sorted));
+    before - anagram_search_lookups.anagrams_sorted_vec.len()
+}
+
+// Shuffle the roots into an order that only depends on the seed, so a run can
+// be repeated. Any order finds every phrase since each root only combines with
+// the ones after it, but it undoes the longest first sort that prunes the
+// search, so exhausting it takes longer.
+// The generator is SplitMix64, which is plenty for picking an order.
+fn shuffle_roots(anagrams_sorted_vec: &mut [String], seed: u64) {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+
+    // Fisher-Yates, the slight bias of the modulo doesn't matter here.
+    for i in (1..anagrams_sorted_vec.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        anagrams_sorted_vec.swap(i, j);
+    }
+}
+
+// Print the solutions as CSV in the order they were found, meant to be the
+// last thing printed so the rows can be cut from the end of the output.
+fn print_solutions_csv(prefix: &str, metrics: &AnagramMetrics, start_time: Instant) {
+    let mut solutions: Vec<(&String, &AnagramSolutionMetrics)> = metrics.anagram_phrase_solution.iter().collect();
+    solutions.sort_by_key(|(_, solution_metrics)| solution_metrics.anagram_phrase_time);
+
+    say!(prefix, "phrase,digest,duration_ms");
+    for (phrase, solution_metrics) in solutions {
+        say!(prefix, "{},{},{:.3}",
+            csv_field(phrase),
+            solution_metrics.anagram_phrase_checksum,
+            solution_metrics.anagram_phrase_time.duration_since(start_time).as_secs_f64() * 1000.0);
+    }
+}
+
+// Quote a CSV field when it has a comma, quote or line break in it, which a
+// wordlist entry with punctuation could.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+// Print how long loading and filtering the wordlist took apart from the search
+// itself, to tell whether the time goes to parsing or searching.
+fn print_timings(prefix: &str, load_time: Duration, start_time: Instant) {
+    say!(prefix, "Load time: {:?}, Search time: {:?}", load_time, start_time.elapsed());
+}
+
+/// The metrics written by --metrics-out, the totals of AnagramMetrics along
+/// with the timings, in units that read the same across runs.
+#[derive(Debug, Serialize)]
+struct MetricsReport {
+    all_solved: bool,
+    load_seconds: f64,
+    search_seconds: f64,
+    phrases_per_second: f64,
+    roots_exhausted: u64,
+    phrases_found: u64,
+    phrases_incomplete: u64,
+    phrases_counted: u64,
+    phrase_max_depth: u32,
+    recursion_max_depth: u32,
+    branches_pruned: u64,
+    branches_smart_pruned: u64,
+    filter: FilterStats,
+    solutions: Vec<SolutionReport>, // In the order they were found
+}
+
+#[derive(Debug, Serialize)]
+struct SolutionReport {
+    phrase: String,
+    digest: String,
+    seconds_to_find: f64,
+    wildcards: String,
+}
+
+// The final metrics as they're written by --metrics-out.
+fn metrics_report(metrics: &AnagramMetrics, filter_stats: FilterStats, start_time: Instant, load_time: Duration, all_solved: bool) -> MetricsReport {
+    let mut solutions: Vec<SolutionReport> = metrics.anagram_phrase_solution.iter()
+        .map(|(phrase, solution_metrics)| SolutionReport {
+            phrase: phrase.clone(),
+            digest: solution_metrics.anagram_phrase_checksum.to_string(),
+            seconds_to_find: solution_metrics.anagram_phrase_time.duration_since(start_time).as_secs_f64(),
+            wildcards: solution_metrics.anagram_phrase_wildcards.clone(),
+        })
+        .collect();
+    solutions.sort_by(|a, b| a.seconds_to_find.total_cmp(&b.seconds_to_find));
+
+    MetricsReport {
+        all_solved,
+        load_seconds: load_time.as_secs_f64(),
+        search_seconds: start_time.elapsed().as_secs_f64(),
+        phrases_per_second: phrases_per_second(metrics.anagram_phrases_found, start_time.elapsed()),
+        roots_exhausted: metrics.anagram_roots_exhausted,
+        phrases_found: metrics.anagram_phrases_found,
+        phrases_incomplete: metrics.anagram_phrases_incomplete,
+        phrases_counted: metrics.anagram_phrases_counted,
+        phrase_max_depth: metrics.anagram_phrase_max_depth,
+        recursion_max_depth: metrics.anagram_recursion_max_depth,
+        branches_pruned: metrics.anagram_branches_pruned,
+        branches_smart_pruned: metrics.anagram_branches_smart_pruned,
+        filter: filter_stats,
+        solutions,
+    }
+}
+
+// Write the final metrics as JSON, for comparing runs. A file that can't be
+// written is reported but doesn't lose the rest of the output.
+fn write_metrics(path: &str, metrics: &AnagramMetrics, filter_stats: FilterStats, start_time: Instant, load_time: Duration, all_solved: bool) {
+    let report = metrics_report(metrics, filter_stats, start_time, load_time, all_solved);
+    let written = File::create(path)
+        .map_err(|err| err.to_string())
+        .and_then(|f| serde_json::to_writer_pretty(BufWriter::new(f), &report).map_err(|err| err.to_string()));
+    if let Err(err) = written {
+        eprintln!("Could not write the metrics to {}: {}", path, err);
+    }
+}
+
+// Write the steps the traversal recorded as a Graphviz DOT graph. Each node is
+// a sequence of collected sorted anagrams, labelled with the last of them, and
+// hangs off the sequence without it. The steps that didn't fit are dashed and
+// the completed phrases are boxed.
+fn write_dot(path: &str, metrics: &AnagramMetrics) {
+    let dot_id = |path: &[String]| format!("\"{}\"", path.join(" ").replace('\\', "\\\\").replace('"', "\\\""));
+    let mut dot_nodes: Vec<&DotNode> = metrics.anagram_dot_nodes.iter().collect();
+    dot_nodes.sort();
+
+    let written = File::create(path).and_then(|f| {
+        let mut out = BufWriter::new(f);
+        writeln!(out, "digraph anagram_search {{")?;
+        writeln!(out, "    {} [label=\"target\", shape=doublecircle];", dot_id(&[]))?;
+        for dot_node in dot_nodes {
+            let style = match dot_node.kind {
+                DotNodeKind::Fits => "",
+                DotNodeKind::DoesntFit => ", style=dashed, color=gray",
+                DotNodeKind::Complete => ", shape=box, color=green",
+            };
+            let (last, parent) = dot_node.path.split_last().unwrap();
+            writeln!(out, "    {} [label={}{}];", dot_id(&dot_node.path), dot_id(std::slice::from_ref(last)), style)?;
+            writeln!(out, "    {} -> {};", dot_id(parent), dot_id(&dot_node.path))?;
+        }
+        writeln!(out, "}}")?;
+        out.flush()
+    });
+    match written {
+        Ok(()) if metrics.anagram_dot_truncated => eprintln!("The search tree is too big to draw, only the first {} steps were written to {}", DOT_MAX_NODES, path),
+        Ok(()) => {}
+        Err(err) => eprintln!("Could not write the search tree to {}: {}", path, err),
+    }
+}
+
+// Print the share of the root tasks' time each phase took. Permutation is
+// printed without the hashing it includes, other is the rest of the traversal.
+fn print_profile(prefix: &str, anagram_profile: &SearchProfile) {
+    let traverse = anagram_profile.traverse.as_secs_f64().max(f64::MIN_POSITIVE);
+    let share = |phase: Duration| 100.0 * phase.as_secs_f64() / traverse;
+    let permutate = anagram_profile.permutate.saturating_sub(anagram_profile.hash);
+    let other = anagram_profile.traverse.saturating_sub(anagram_profile.chars + anagram_profile.permutate);
+
+    say!(prefix, "--Profile of {:?} in root tasks--", anagram_profile.traverse);
+    say!(prefix, "- Letters (subtract/add): {:.1}% ({:?})", share(anagram_profile.chars), anagram_profile.chars);
+    say!(prefix, "- Permutation: {:.1}% ({:?})", share(permutate), permutate);
+    say!(prefix, "- Hashing: {:.1}% ({:?})", share(anagram_profile.hash), anagram_profile.hash);
+    say!(prefix, "- Other: {:.1}% ({:?})", share(other), other);
+}
+
+// Print the best scoring phrases found, best first.
+fn print_top_phrases(prefix: &str, metrics: &AnagramMetrics) {
+    say!(prefix, "Top phrases:");
+    for (rank, Reverse(scored_phrase)) in metrics.anagram_top_phrases.clone().into_sorted_vec().iter().enumerate() {
+        say!(prefix, "{}. {} (score: {}, words: {})", rank + 1, scored_phrase.phrase, scored_phrase.score, scored_phrase.words);
+    }
+}
+
+// The entry point for the anagram phrase solution.
+// Returns whether every checksum was solved.
+// With index_roots the lookups only hold the roots, and each root task is given
+// the sorted anagrams it can be followed by read from the index.
+fn search_anagram_phrases(
+    mut anagram_search: AnagramSearch,
+    anagram_search_lookups: AnagramSearchLookups,
+    index_roots: Option<&IndexRoots>,
+    filter_stats: FilterStats,
+    load_time: Duration) -> bool {
+    // The resume index in the traversal relies on each root appearing only once.
+    debug_assert_eq!(
+        anagram_search_lookups.anagrams_sorted_vec.len(),
+        anagram_search_lookups.anagrams_sorted_vec.iter().collect::<HashSet<_>>().len(),
+        "anagrams_sorted_vec contains duplicate roots");
+
+    let prefix = anagram_search.options.output_prefix.clone();
+
+    // Technical stuff to control concurrency
+    let num_cores = num_cpus::get();
+    let num_concurrent: usize = anagram_search.options.jobs.unwrap_or(num_cores);
+    let mut count_concurrent: usize = 0;
+    let mut metrics: AnagramMetrics = AnagramMetrics { 
+        anagram_phrase_solution: HashMap::new(),
+        anagram_phrases_incomplete: 0,
+        anagram_roots_exhausted: 0,
+        anagram_phrases_found: 0, 
+        anagram_phrases_counted: 0,
+        anagram_phrase_max_depth: 0,
+        anagram_recursion_max_depth: 0,
+        anagram_branches_pruned: 0,
+        anagram_branches_smart_pruned: 0,
+        anagram_top_phrases: BinaryHeap::new(),
+        anagram_profile: SearchProfile::default(),
+        anagram_dot_nodes: Vec::new(),
+        anagram_dot_truncated: false,
+        is_done: false,};
+
+    // Performance measuring metrics. Keep this immediately above the loop.
+    // For best measurements, disable the print statements until the end.
+    let start_time: Instant = Instant::now();
+
+    let (tx, rx): (Sender<AnagramMetrics>, Receiver<AnagramMetrics>) = channel();
+
+    // Loop over all sorted anagrams and insert them recursively.
+    // The actual words from the anagrams will permutate later.
+    for (current_anagram_sorted_index, current_anagram_sorted) in anagram_search_lookups.anagrams_sorted_vec.iter().enumerate() {
+        // Keep the user informed of the progress, the throughput only counts the exhausted roots
+        say!(&prefix, "Processing root: {}/{}, anagram sorted: {}, len: {}, phrases/s: {:.0}",
+            current_anagram_sorted_index+1, // Use natural numbers
+            anagram_search_lookups.anagrams_sorted_vec.len(),
+            current_anagram_sorted,
+            current_anagram_sorted.len(),
+            phrases_per_second(metrics.anagram_phrases_found, start_time.elapsed()));
+
+        let current_anagram_char_count: &HashMap<char, u32> = anagram_search_lookups.anagrams_sorted_chars.get(current_anagram_sorted).unwrap();
+
+        if !subtract_search_chars(&mut anagram_search, current_anagram_char_count) {
+            if anagram_search.options.smart_prune {
+                remove_letter_suppliers(&mut anagram_search.letter_suppliers, current_anagram_char_count, &HashMap::new());
+            }
+            continue;
+        }
+
+        // The cloning is necessary for the asynchronous operations.
+        // The new task only needs to compare against what hasn't been solved yet.
+        let mut anagram_search_clone: AnagramSearch = anagram_search.clone();
+        let solved = anagram_search.solved.lock().unwrap();
+        anagram_search_clone.unsolved.retain(|checksum| !solved.contains(checksum));
+        drop(solved);
+        let (anagram_search_lookups_clone, resume_index): (AnagramSearchLookups, usize) = match index_roots {
+            Some(index_roots) => index_roots.root_lookups(current_anagram_sorted_index, &anagram_search, &anagram_search_lookups),
+            None => (anagram_search_lookups.clone(), current_anagram_sorted_index),
+        };
+        let tx_clone: Sender<AnagramMetrics> = tx.clone();
+        let anagram_sorted_clone: String = current_anagram_sorted.clone();
+
+        task::spawn(async move {
+            async_traverse_anagram_phrases(
+                anagram_search_clone,
+                anagram_search_lookups_clone,
+                anagram_sorted_clone,
+                resume_index,
+                tx_clone,
+            ).await});
+                        
+        add_search_chars(&mut anagram_search, current_anagram_char_count);
+        // The roots after this one no longer have it to supply their letters.
+        if anagram_search.options.smart_prune {
+            remove_letter_suppliers(&mut anagram_search.letter_suppliers, current_anagram_char_count, &HashMap::new());
+        }
+
+        count_concurrent += 1;
+        if count_concurrent >= num_concurrent {
+            let metrics_received = rx.recv().unwrap();
+            if anagram_search.options.stream_solutions {
+                print_new_solutions(&prefix, &metrics, &metrics_received, start_time);
+            }
+            if metrics_received.is_done {
+                count_concurrent -= 1;
+            }
+            add_metrics(&mut metrics, metrics_received, &anagram_search.options);
+            if !anagram_search.options.all_matches && all_checksums_solved(&metrics, &anagram_search_lookups.md5_checksums) {
+                say!(&prefix,
+                    "--Metrics from exhausted anagram roots--\n\
+                    - Anagram Roots Exhausted: {}\n\
+                    - Phrases Computed: {}\n\
+                    - Phrases Invalid: {}\n\
+                    - Max Phrase Length: {}\n\
+                    - Max Recursion Depth: {}\n\
+                    - Branches Pruned: {}\n\
+                    - Phrases per Second: {:.0}\n\
+                    - Tasks in progress (no metrics reported): {}",
+                    metrics.anagram_roots_exhausted,
+                    metrics.anagram_phrases_found.to_formatted_string(&Locale::en),
+                    metrics.anagram_phrases_incomplete.to_formatted_string(&Locale::en),
+                    metrics.anagram_phrase_max_depth,
+                    metrics.anagram_recursion_max_depth,
+                    metrics.anagram_branches_pruned.to_formatted_string(&Locale::en),
+                    phrases_per_second(metrics.anagram_phrases_found, start_time.elapsed()),
+                    count_concurrent);
+                report_search_end(&anagram_search.options, &metrics, filter_stats, start_time, load_time, true);
+                // Other threads may be running, tell them to give up rather than waiting for them.
+                anagram_search.stop.store(true, AtomicOrdering::Relaxed);
+                return true;
+            }
+        }
+    }
+
+    // Every root has been spawned, wait on the tasks still running. There may be
+    // fewer of them than jobs, so a message is waited on no matter how many are left.
+    while count_concurrent > 0 {
+        let metrics_received = rx.recv().unwrap();
+        if anagram_search.options.stream_solutions {
+            print_new_solutions(&prefix, &metrics, &metrics_received, start_time);
+        }
+        if metrics_received.is_done {
+            count_concurrent -= 1;
+        }
+        add_metrics(&mut metrics, metrics_received, &anagram_search.options);
+        if !anagram_search.options.all_matches && all_checksums_solved(&metrics, &anagram_search_lookups.md5_checksums) {
+            say!(&prefix, "Found all solutions in time elapsed: {:?}", start_time.elapsed());
+            say!(&prefix, "Phrases Computed: {}, Phrases per Second: {:.0}, Max Phrase Length: {}, Max Recursion Depth: {}",
+                metrics.anagram_phrases_found.to_formatted_string(&Locale::en),
+                phrases_per_second(metrics.anagram_phrases_found, start_time.elapsed()),
+                metrics.anagram_phrase_max_depth,
+                metrics.anagram_recursion_max_depth);
+            report_search_end(&anagram_search.options, &metrics, filter_stats, start_time, load_time, true);
+            anagram_search.stop.store(true, AtomicOrdering::Relaxed);
+            return true;
+        }
+    }
+
+    say!(&prefix, "Exhausted every root in time elapsed: {:?}, Phrases Computed: {}, Phrases per Second: {:.0}",
+        start_time.elapsed(),
+        metrics.anagram_phrases_found.to_formatted_string(&Locale::en),
+        phrases_per_second(metrics.anagram_phrases_found, start_time.elapsed()));
+    if anagram_search.options.count_only {
+        say!(&prefix, "Phrases Counted: {}, time elapsed: {:?}",
+            metrics.anagram_phrases_counted.to_formatted_string(&Locale::en),
+            start_time.elapsed());
+    }
+    if anagram_search.options.max_recursion.is_some() {
+        say!(&prefix, "Max Recursion Depth: {}, Branches Pruned: {}",
+            metrics.anagram_recursion_max_depth,
+            metrics.anagram_branches_pruned.to_formatted_string(&Locale::en));
+    }
+    if anagram_search.options.smart_prune {
+        say!(&prefix, "Branches Smart Pruned: {}", metrics.anagram_branches_smart_pruned.to_formatted_string(&Locale::en));
+    }
+    // Every root was searched for every match, so the search only failed if a checksum never matched.
+    let all_solved = anagram_search.options.all_matches && all_checksums_solved(&metrics, &anagram_search_lookups.md5_checksums);
+
+    report_search_end(&anagram_search.options, &metrics, filter_stats, start_time, load_time, all_solved);
+
+    all_solved
+}
+
+// Whether a phrase was found for every checksum. Phrases are counted by the
+// checksum they solved, and without any checksums there's nothing to solve.
+fn all_checksums_solved(metrics: &AnagramMetrics, md5_checksums: &HashSet<Digest>) -> bool {
+    !md5_checksums.is_empty() && md5_checksums.iter().all(|checksum|
+        metrics.anagram_phrase_solution.values().any(|solution_metrics| solution_metrics.anagram_phrase_checksum == *checksum))
+}
+
+// Everything printed or written once the search is over, the same whichever
+// way it ended. The CSV rows stay last so they can be cut from the end.
+fn report_search_end(options: &AnagramOptions, metrics: &AnagramMetrics, filter_stats: FilterStats, start_time: Instant, load_time: Duration, all_solved: bool) {
+    // Every match is listed by digest instead, after the rest of the report.
+    if !options.all_matches && options.output_format == OutputFormat::Text {
+        print_solutions(&options.output_prefix, metrics, start_time, options.sort_by_time);
+    }
+    let prefix = &options.output_prefix;
+    print_timings(prefix, load_time, start_time);
+    if let Some(metrics_out) = &options.metrics_out {
+        write_metrics(metrics_out, metrics, filter_stats, start_time, load_time, all_solved);
+    }
+    if let Some(dot) = &options.dot {
+        write_dot(dot, metrics);
+    }
+    if options.profile {
+        print_profile(prefix, &metrics.anagram_profile);
+    }
+    if options.top_phrases.is_some() {
+        print_top_phrases(prefix, metrics);
+    }
+    if options.all_matches && options.output_format == OutputFormat::Text {
+        print_matches_by_digest(prefix, metrics);
+    }
+    if options.output_format == OutputFormat::Csv {
+        print_solutions_csv(prefix, metrics, start_time);
+    }
+}
+
+// Print the phrases found with the checksums they solved, which may be only
+// some of them when the search ran out of roots first.
+fn print_solutions(prefix: &str, metrics: &AnagramMetrics, start_time: Instant, sort_by_time: bool) {
+    for (phrase, solution_metrics) in solutions_in_order(metrics, sort_by_time) {
+        say!(prefix, "{} : {}, time to find: {:?}{}", solution_metrics.anagram_phrase_checksum, phrase, solution_metrics.anagram_phrase_time.duration_since(start_time), wildcards_note(solution_metrics));
+    }
+}
+
+// The solutions in the order they were found with --sort-by-time, otherwise in
+// whatever order the map keeps them.
+fn solutions_in_order(metrics: &AnagramMetrics, sort_by_time: bool) -> Vec<(&String, &AnagramSolutionMetrics)> {
+    let mut solutions: Vec<(&String, &AnagramSolutionMetrics)> = metrics.anagram_phrase_solution.iter().collect();
+    if sort_by_time {
+        solutions.sort_by_key(|(_, solution_metrics)| solution_metrics.anagram_phrase_time);
+    }
+    solutions
+}
+
+// Print every phrase found for each checksum, for --all-matches where a
+// checksum may have more than one.
+fn print_matches_by_digest(prefix: &str, metrics: &AnagramMetrics) {
+    let mut matches: HashMap<&Digest, Vec<&String>> = HashMap::new();
+    for (phrase, solution_metrics) in metrics.anagram_phrase_solution.iter() {
+        matches.entry(&solution_metrics.anagram_phrase_checksum).or_default().push(phrase);
+    }
+
+    let mut matches: Vec<(&Digest, Vec<&String>)> = matches.into_iter().collect();
+    matches.sort_by_key(|(digest, _)| digest.to_string());
+    for (digest, mut phrases) in matches {
+        phrases.sort();
+        say!(prefix, "{} : {} matches : {}", digest, phrases.len(), phrases.iter().map(|phrase| phrase.as_str()).collect::<Vec<&str>>().join(", "));
+    }
+}
+
+
+async fn async_traverse_anagram_phrases(
+    mut anagram_search: AnagramSearch,
+    anagram_search_lookups: AnagramSearchLookups,
+    anagram_root: String,
+    resume_index: usize,
+    tx: Sender<AnagramMetrics>) {
+
+    let mut anagram_metrics: AnagramMetrics = AnagramMetrics{
+        anagram_phrases_incomplete: 0,
+        anagram_phrase_solution: HashMap::new(),
+        anagram_roots_exhausted: 0,
+        anagram_phrase_max_depth:0,
+        anagram_recursion_max_depth:0,
+        anagram_branches_pruned:0,
+        anagram_branches_smart_pruned:0,
+        anagram_top_phrases: BinaryHeap::new(),
+        anagram_profile: SearchProfile::default(),
+        anagram_dot_nodes: Vec::new(),
+        anagram_dot_truncated: false,
+        anagram_phrases_found:0,
+        anagram_phrases_counted:0,
+        is_done:false,};
+
+    trace!("{} is the root", anagram_root);
+    let mut anagram_collected_ref: Vec<&String> = Vec::new();
+    anagram_collected_ref.push(&anagram_root);
+    if anagram_search.options.dot.is_some() {
+        let complete = anagram_search.anagram_chars_search.is_empty() && anagram_search.wildcards_left == 0;
+        record_dot_node(&mut anagram_metrics, &anagram_collected_ref, if complete { DotNodeKind::Complete } else { DotNodeKind::Fits });
+    }
+
+    let traverse_started = anagram_search.options.profile.then(Instant::now);
+    // The traversal only checks the letters a passed sorted anagram supplied,
+    // so check every letter the root left over once before starting.
+    if anagram_search.options.smart_prune && anagram_search.anagram_chars_search.keys()
+        .any(|c| anagram_search.letter_suppliers.get(c).is_none_or(|suppliers| *suppliers == 0)) {
+        trace!("{} leaves a letter nothing can supply", anagram_root);
+        anagram_metrics.anagram_branches_smart_pruned += 1;
+    } else {
+        traverse_anagram_phrases(
+                &mut anagram_search,
+                &anagram_search_lookups,
+                &mut anagram_metrics,
+                &mut anagram_collected_ref,
+                resume_index,
+                &tx);
+    }
+    if let Some(started) = traverse_started {
+        anagram_metrics.anagram_profile.traverse += started.elapsed();
+    }
+
+    // Send a message to the parent task that this task is done.
+    // The search may have already finished and stopped listening.
+    anagram_metrics.is_done = true;
+    tx.send(anagram_metrics).ok();
+}
+
+fn traverse_anagram_phrases<'a>(
+                anagram_search: &mut AnagramSearch,
+                anagram_search_lookups: &'a AnagramSearchLookups,
+                anagram_metrics: &mut AnagramMetrics,
+                anagrams_collected_ref: &mut Vec<&'a String>,
+                resume_index: usize,
+                tx: &Sender<AnagramMetrics>) {
+
+    if anagram_search.stop.load(AtomicOrdering::Relaxed) {
+        return;
+    }
+
+    // Every level of recursion collects one more sorted anagram.
+    let recursion_depth = anagrams_collected_ref.len();
+    if recursion_depth > anagram_metrics.anagram_recursion_max_depth as usize {
+        anagram_metrics.anagram_recursion_max_depth = recursion_depth as u32;
+    }
+    if anagram_search.options.max_recursion.is_some_and(|max_recursion| recursion_depth > max_recursion) {
+        anagram_metrics.anagram_branches_pruned += 1;
+        return;
+    }
+
+    // Every wildcard has to stand in for a letter too, like the letters they're blanks among.
+    if anagram_search.anagram_chars_search.is_empty() && anagram_search.wildcards_left == 0 {
+        trace!("{:indent$}complete: {:?}", "", anagrams_collected_ref, indent = recursion_depth * 2);
+        complete_anagram_phrases(
+            anagram_search,
+            anagram_search_lookups,
+            anagram_metrics,
+            anagrams_collected_ref,
+            tx);
+        return;
+    }
+
+    // The phrase already has as many words as it may have, in subset mode
+    // that's as complete as it gets.
+    if anagram_search.options.max_depth.is_some_and(|max_depth| recursion_depth >= max_depth) {
+        if anagram_search.options.subset {
+            complete_anagram_phrases(
+                anagram_search,
+                anagram_search_lookups,
+                anagram_metrics,
+                anagrams_collected_ref,
+                tx);
+        }
+        return;
+    }
+
+    let mut extended = false;
+    let anagrams_sorted_vec_ref = &anagram_search_lookups.anagrams_sorted_vec;
+    // With --smart-prune, the sorted anagrams passed by are taken out of the letter
+    // suppliers, and the rest of the branch is given up once a letter still needed
+    // has none left. They're put back before returning to the caller.
+    let mut suppliers_removed = resume_index;
+    for (anagram_sorted_index, anagram_sorted) in anagrams_sorted_vec_ref.iter().skip(resume_index).enumerate() {
+        if anagram_search.options.smart_prune && anagram_sorted_index > 0 {
+            let passed_char_count = &anagram_search_lookups.anagrams_sorted_chars[&anagrams_sorted_vec_ref[suppliers_removed]];
+            suppliers_removed += 1;
+            if !remove_letter_suppliers(&mut anagram_search.letter_suppliers, passed_char_count, &anagram_search.anagram_chars_search) {
+                trace!("{:indent$}no letter suppliers left at {}", "", anagram_sorted, indent = recursion_depth * 2);
+                anagram_metrics.anagram_branches_smart_pruned += 1;
+                break;
+            }
+        }
+
+        let anagram_char_count = anagram_search_lookups.anagrams_sorted_chars.get(anagram_sorted).unwrap();
+        let chars_started = anagram_search.options.profile.then(Instant::now);
+        let fits = subtract_search_chars(anagram_search, anagram_char_count);
+        if let Some(started) = chars_started {
+            anagram_metrics.anagram_profile.chars += started.elapsed();
+        }
+        if !fits {
+            trace!("{:indent$}{} doesn't fit", "", anagram_sorted, indent = recursion_depth * 2);
+            anagram_metrics.anagram_phrases_incomplete += 1;
+            if anagram_search.options.dot.is_some() {
+                let mut attempted = anagrams_collected_ref.clone();
+                attempted.push(anagram_sorted);
+                record_dot_node(anagram_metrics, &attempted, DotNodeKind::DoesntFit);
+            }
+            continue;
+        }
+
+        trace!("{:indent$}{} fits", "", anagram_sorted, indent = recursion_depth * 2);
+        extended = true;
+        anagrams_collected_ref.push(anagram_sorted);
+        if anagram_search.options.dot.is_some() {
+            let complete = anagram_search.anagram_chars_search.is_empty() && anagram_search.wildcards_left == 0;
+            record_dot_node(anagram_metrics, anagrams_collected_ref, if complete { DotNodeKind::Complete } else { DotNodeKind::Fits });
+        }
+
+        traverse_anagram_phrases(
+            anagram_search,
+            anagram_search_lookups,
+            anagram_metrics,
+            anagrams_collected_ref,
+            resume_index + anagram_sorted_index,
+            tx);
+            
+        anagrams_collected_ref.pop();
+        trace!("{:indent$}{} backtracked", "", anagram_sorted, indent = recursion_depth * 2);
+
+        let chars_started = anagram_search.options.profile.then(Instant::now);
+        add_search_chars(anagram_search, anagram_char_count);
+        if let Some(started) = chars_started {
+            anagram_metrics.anagram_profile.chars += started.elapsed();
+        }
+    }
+    for anagram_sorted in &anagrams_sorted_vec_ref[resume_index..suppliers_removed] {
+        for c in anagram_search_lookups.anagrams_sorted_chars[anagram_sorted].keys() {
+            *anagram_search.letter_suppliers.get_mut(c).unwrap() += 1;
+        }
+    }
+
+    // In subset mode the phrase is complete once no word fits in the letters left over.
+    // The words before the resume index have to be checked as well, a phrase one of them
+    // still fits in is found in full from another branch.
+    if anagram_search.options.subset && !extended
+        && !anagrams_sorted_vec_ref[..resume_index].iter().any(|anagram_sorted| contains_chars_wild(
+            &anagram_search.anagram_chars_search,
+            &anagram_search_lookups.anagrams_sorted_chars[anagram_sorted],
+            anagram_search.wildcards_left)) {
+        complete_anagram_phrases(
+            anagram_search,
+            anagram_search_lookups,
+            anagram_metrics,
+            anagrams_collected_ref,
+            tx);
+    }
+}
+
+// Record a step of the traversal for --dot, the collected sorted anagrams with
+// the one tried last.
+fn record_dot_node(anagram_metrics: &mut AnagramMetrics, anagrams_collected: &[&String], kind: DotNodeKind) {
+    if anagram_metrics.anagram_dot_nodes.len() >= DOT_MAX_NODES {
+        anagram_metrics.anagram_dot_truncated = true;
+        return;
+    }
+    anagram_metrics.anagram_dot_nodes.push(DotNode {
+        path: anagrams_collected.iter().map(|anagram_sorted| anagram_sorted.to_string()).collect(),
+        kind,
+    });
+}
+
+// The collected sorted anagrams make complete phrases, count them or
+// permutate them into phrases to test.
+fn complete_anagram_phrases<'a>(
+                anagram_search: &mut AnagramSearch,
+                anagram_search_lookups: &'a AnagramSearchLookups,
+                anagram_metrics: &mut AnagramMetrics,
+                anagrams_collected_ref: &mut Vec<&'a String>,
+                tx: &Sender<AnagramMetrics>) {
+
+    // The sorted anagrams have no spaces, so their lengths add up to the phrase's letters.
+    let mut phrase_len: usize = 0;
+    for anagram_sorted in anagrams_collected_ref.iter() {
+        phrase_len += anagram_sorted.len();
+    }
+    if phrase_len < anagram_search.options.min_phrase_len {
+        return;
+    }
+
+    if anagrams_collected_ref.len() > anagram_metrics.anagram_phrase_max_depth.try_into().unwrap() {
+        anagram_metrics.anagram_phrase_max_depth = anagrams_collected_ref.len().try_into().unwrap();
+    }
+
+    if anagram_search.options.count_only {
+        anagram_metrics.anagram_phrases_counted += count_anagram_phrases(anagram_search_lookups, anagrams_collected_ref, anagram_search.options.no_word_order);
+        return;
+    }
+
+    // Room for the words and a space after each
+    let capacity: usize = phrase_len + anagrams_collected_ref.len();
+    let mut anagram_phrase = String::with_capacity(capacity);
+    let mut anagram_phrase_vec: Vec<&String> = Vec::new();
+    let permutate_started = anagram_search.options.profile.then(Instant::now);
+    if anagram_search.options.no_word_order {
+        // The collected order is already the canonical one, only the words are chosen.
+        permutate_anagram_words(
+            anagram_search,
+            anagram_search_lookups,
+            anagram_metrics,
+            anagrams_collected_ref,
+            &mut anagram_phrase_vec,
+            &mut anagram_phrase,
+            0,
+            tx);
+    } else {
+        permutate_anagram_sorted(
+            anagram_search,
+            anagram_search_lookups,
+            anagram_metrics,
+            anagrams_collected_ref,
+            &mut anagram_phrase_vec,
+            &mut anagram_phrase,
+            anagrams_collected_ref.len(),
+            tx);
+    }
+    if let Some(started) = permutate_started {
+        anagram_metrics.anagram_profile.permutate += started.elapsed();
+    }
+}
+
+// Count the distinct phrases the collected sorted anagrams make without building them.
+// That's the distinct orderings of the sorted anagrams, a repeated one can't be
+// told apart from itself, times every choice of word for each of them.
+// Without word order there's one ordering, and a sorted anagram repeated r times
+// picks a multiset of r of its words instead.
+fn count_anagram_phrases(anagram_search_lookups: &AnagramSearchLookups, anagrams_collected: &[&String], no_word_order: bool) -> u64 {
+    let mut count: u64 = 1;
+    let mut repeats: HashMap<&String, u64> = HashMap::new();
+
+    for (position, anagram_sorted) in anagrams_collected.iter().enumerate() {
+        let repeat = repeats.entry(anagram_sorted).or_insert(0);
+        *repeat += 1;
+        let words = anagram_search_lookups.anagrams_sorted_map.get(*anagram_sorted).map_or(0, |words| words.len() as u64);
+
+        // Building the multinomial one position at a time keeps every step a whole number.
+        if no_word_order {
+            count = count * (words + *repeat - 1) / *repeat;
+        } else {
+            count = count * (position as u64 + 1) / *repeat * words;
+        }
+    }
+
+    count
+}
+
+#[allow(clippy::too_many_arguments)]
+fn permutate_anagram_sorted<'a>(
+    anagram_search: &mut AnagramSearch, 
+    anagram_search_lookups: &'a AnagramSearchLookups,
+    anagram_metrics: &mut AnagramMetrics,
+    anagrams_collected: &mut Vec<&String>,
+    anagram_phrase_vec: &mut Vec<&'a String>,
+    anagram_phrase: &mut String,
+    size: usize,
+    tx: &Sender<AnagramMetrics>) {
+
+    if size == 1 {
+        permutate_anagram_words(
+            anagram_search,
+            anagram_search_lookups,
+            anagram_metrics,
+            anagrams_collected,
+            anagram_phrase_vec,
+            anagram_phrase,
+            0,
+            tx);
+        return;
+    }
+
+    permutate_anagram_sorted(anagram_search,
+        anagram_search_lookups,
+        anagram_metrics,
+        anagrams_collected,
+        anagram_phrase_vec,
+        anagram_phrase,
+        size - 1,
+        tx);
+
+    for idx in 0..size-1 {        
+        if size & 1 == 1 {
+            anagrams_collected.swap(0, size-1);
+        } else {
+            anagrams_collected.swap(idx, size - 1);
+        }
+
+        permutate_anagram_sorted(anagram_search,
+                                   anagram_search_lookups,
+                                   anagram_metrics,
+                                   anagrams_collected,
+                                   anagram_phrase_vec,
+                                   anagram_phrase,
+                                   size - 1,
+                                   tx);
+    }
+
+    if size == anagrams_collected.len() {
+        if anagrams_collected.len() & 1 == 1 {
+            anagrams_collected.swap(0, size - 1);
+        } else {
+            anagrams_collected.rotate_right(1);
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn permutate_anagram_words<'a> (
+    anagram_search: &mut AnagramSearch,
+    anagram_search_lookups: &'a AnagramSearchLookups,
+    anagram_metrics: &mut AnagramMetrics,
+    anagrams_collected: &mut Vec<&String>,
+    anagram_phrase_vec: &mut Vec<&'a String>,
+    anagram_phrase_string: &mut String,
+    resume_idx: usize,
+    tx: &Sender<AnagramMetrics>) {
+
+    if resume_idx >= anagrams_collected.len() {
+        anagram_phrase_string.clear();
+        for word in anagram_phrase_vec.iter() {
+            anagram_phrase_string.push_str(word);
+            anagram_phrase_string.push(' ');
+        }
+        // Get rid of the very last space
+        anagram_phrase_string.pop();
+
+        if !phrase_allowed(anagram_phrase_string, &anagram_search.options) {
+            return;
+        }
+
+        if let Some(top_phrases) = anagram_search.options.top_phrases {
+            let scored_phrase = ScoredPhrase {
+                score: score_phrase(anagram_phrase_vec, &anagram_search_lookups.word_scores),
+                words: anagram_phrase_vec.len(),
+                phrase: anagram_phrase_string.clone(),
+            };
+            push_top_phrase(&mut anagram_metrics.anagram_top_phrases, scored_phrase, top_phrases);
+        }
+
+        test_md5_checksums(
+            anagram_phrase_string,
+            &anagram_search_lookups.md5_checksums,
+            anagram_search,
+            anagram_metrics,
+            tx);
+        return;
+    }
+
+    let anagram_sorted: &String = anagrams_collected.index(resume_idx);
+    let words = anagram_search_lookups.anagrams_sorted_map.get(anagram_sorted);
+
+    if words.is_none() {
+        return;
+    }
+
+    // A sorted anagram collected more than once would make the same set of words
+    // in every order, so without word order its words are only chosen in sorted order.
+    let repeated = anagram_search.options.no_word_order
+        && resume_idx > 0 && anagrams_collected[resume_idx - 1] == anagram_sorted;
+
+    for word in words.unwrap().iter() {
+        if repeated && anagram_phrase_vec.last().is_some_and(|previous| word < *previous) {
+            continue;
+        }
+        anagram_phrase_vec.push(word);
+
+        permutate_anagram_words(
+                anagram_search,
+                anagram_search_lookups,
+                anagram_metrics,
+                anagrams_collected,
+                anagram_phrase_vec,
+                anagram_phrase_string,
+    resume_idx + 1,
+                tx);
+
+        anagram_phrase_vec.pop();
+    }    
+}
+
+// Count how many of the sorted anagrams have each letter, the suppliers --smart-prune starts from.
+fn count_letter_suppliers(anagram_search_lookups: &AnagramSearchLookups) -> HashMap<char, u32> {
+    let mut letter_suppliers: HashMap<char, u32> = HashMap::new();
+    for anagram_sorted in anagram_search_lookups.anagrams_sorted_vec.iter() {
+        for c in anagram_search_lookups.anagrams_sorted_chars[anagram_sorted].keys() {
+            *letter_suppliers.entry(*c).or_insert(0) += 1;
+        }
+    }
+    letter_suppliers
+}
+
+// Take a sorted anagram out of the letter suppliers.
+// Returns false when one of its letters is still needed but nothing supplies it any more.
+fn remove_letter_suppliers(letter_suppliers: &mut HashMap<char, u32>, anagram_char_count: &HashMap<char, u32>, needed: &HashMap<char, u32>) -> bool {
+    let mut supplied = true;
+    for c in anagram_char_count.keys() {
+        let suppliers = letter_suppliers.get_mut(c).unwrap();
+        *suppliers -= 1;
+        if *suppliers == 0 && needed.contains_key(c) {
+            supplied = false;
+        }
+    }
+    supplied
+}
+
+// A phrase is only considered when it matches every allow pattern and none of the deny patterns,
+// and starts and ends with the required letters. The letters apply to the phrase in the order
+// it would be printed, so the same words in another order may pass where this one didn't.
+fn phrase_allowed(phrase: &str, options: &AnagramOptions) -> bool {
+    options.first_letter.is_none_or(|first_letter| phrase.starts_with(first_letter))
+        && options.last_letter.is_none_or(|last_letter| phrase.ends_with(last_letter))
+        && options.phrase_allow.iter().all(|regex| regex.is_match(phrase))
+        && !options.phrase_deny.iter().any(|regex| regex.is_match(phrase))
+}
+
+// The digest compared to the checksums, of the phrase as built unless the
+// puzzle's checksums were made from it uppercased.
+fn phrase_digest(phrase: &str, uppercase_hash: bool) -> Digest {
+    if uppercase_hash { digest_of(&phrase.to_uppercase()) } else { digest_of(phrase) }
+}
+
+// Test if the anagram phrase matches any of the remaining MD5 checksums.
+// With print_digests every phrase is printed with its digest, which turns
+// the search into a digest generator for puzzle authors.
+// A solved checksum is dropped from the task's own set so later phrases aren't
+// compared against it. One another task already solved isn't reported again,
+// and once every checksum is solved all the tasks are told to stop.
+fn test_md5_checksums(
+    phrase: &String,
+    md5_checksums: &HashSet<Digest>,
+    anagram_search: &mut AnagramSearch,
+    anagram_metrics: &mut AnagramMetrics,
+    tx: &Sender<AnagramMetrics>) {
+
+    anagram_metrics.anagram_phrases_found += 1;
+    let hash_started = anagram_search.options.profile.then(Instant::now);
+    let digest = phrase_digest(phrase, anagram_search.options.uppercase_hash);
+    // Looking for every match means a solved checksum still has to be compared.
+    let solved_here = if anagram_search.options.all_matches {
+        md5_checksums.contains(&digest)
+    } else {
+        anagram_search.unsolved.remove(&digest)
+    };
+    if let Some(started) = hash_started {
+        anagram_metrics.anagram_profile.hash += started.elapsed();
+    }
+
+    if anagram_search.options.print_digests {
+        say!(&anagram_search.options.output_prefix, "{} : {}", phrase, digest);
+    }
+
+    // Near misses are only looked for when asked, they're a debugging aid for
+    // a phrase that should match but is formatted a little differently.
+    if anagram_search.options.prefix_match > 0 && !md5_checksums.contains(&digest) {
+        for checksum in md5_checksums.iter() {
+            if digest.prefix_matches(checksum, anagram_search.options.prefix_match) {
+                say!(&anagram_search.options.output_prefix, "Near miss: {} : {}, starts like {}", phrase, digest, checksum);
+            }
+        }
+    }
+
+    if solved_here {
+        // Only lock on a match, which is rare, so the hot path stays lock free.
+        let mut solved = anagram_search.solved.lock().unwrap();
+        if !solved.insert(digest.clone()) && !anagram_search.options.all_matches {
+            return;
+        }
+        if solved.len() >= md5_checksums.len() && !anagram_search.options.all_matches {
+            anagram_search.stop.store(true, AtomicOrdering::Relaxed);
+        }
+        drop(solved);
+
+        //println!("Found phrase: {}, with md5: {}", phrase, digest);
+        let anagram_solution_metric: AnagramSolutionMetrics = AnagramSolutionMetrics {
+            anagram_phrase_checksum: digest,
+            anagram_phrase_time: std::time::Instant::now(),
+            anagram_phrase_wildcards: get_anagram_sorted(&anagram_search.wildcard_letters.iter()
+                .flat_map(|(c, count)| std::iter::repeat_n(*c, *count as usize))
+                .collect::<String>()),
+        };
+        anagram_metrics.anagram_phrase_solution.insert(phrase.clone(), anagram_solution_metric);
+        tx.send(anagram_metrics.clone()).ok();
+    }
+}
+
+// Print the target the way the search sees it, the sorted key without spaces
+// and how many of each letter it has, to check what the phrase was parsed as.
+fn print_target_key(prefix: &str, anagram_chars: &HashMap<char, u32>) {
+    let mut chars: Vec<(&char, &u32)> = anagram_chars.iter().collect();
+    chars.sort();
+
+    let key: String = chars.iter().flat_map(|(c, count)| std::iter::repeat_n(**c, **count as usize)).collect();
+    let counts: Vec<String> = chars.iter().map(|(c, count)| format!("{}:{}", c, count)).collect();
+    say!(prefix, "Target key: {}, letters: {}", key, key.chars().count());
+    say!(prefix, "Target counts: {}", counts.join(" "));
+}
+
+/// What the search reads from files besides the target and wordlist, read
+/// before it starts so a file that can't be read is reported up front.
+#[derive(Clone, Debug, Default)]
+struct SearchFiles {
+    word_scores: HashMap<String, u64>, // Word -> Score, from --word-scores
+    skipped_roots: HashSet<String>, // The sorted keys of --skip-root and --skip-roots-file
+}
+
+fn read_search_files(options: &AnagramOptions) -> Result<SearchFiles, String> {
+    let mut search_files = SearchFiles::default();
+    if let Some(word_scores) = &options.word_scores {
+        search_files.word_scores = get_word_scores(word_scores)?;
+    }
+    search_files.skipped_roots = options.skip_roots.iter().map(|anagram_sorted| get_anagram_sorted(anagram_sorted)).collect();
+    if let Some(skip_roots_file) = &options.skip_roots_file {
+        search_files.skipped_roots.extend(get_skip_roots(skip_roots_file)?);
+    }
+    Ok(search_files)
+}
+
+// Filter the loaded anagram map down to the anagram and search it for the checksums.
+// The load time is however long it took to read the wordlist, the filtering is added to it.
+// Returns whether every checksum was solved.
+fn run_anagram_search(
+    anagram_map: &HashMap<String, HashSet<String>>,
+    anagram_chars: HashMap<char, u32>,
+    md5_checksums: HashSet<Digest>,
+    search_files: SearchFiles,
+    options: AnagramOptions,
+    load_time: Duration) -> bool {
+
+    let filter_time = Instant::now();
+
+    if options.show_target_key {
+        print_target_key(&options.output_prefix, &anagram_chars);
+    }
+
+    // The search works on sorted keys, so compact ones are expanded, but only
+    // for the words that fit since the expanded keys are the larger ones.
+    let anagram_map_len = anagram_map.len();
+    let expanded_map: HashMap<String, HashSet<String>>;
+    let anagram_map = if options.compact_keys {
+        expanded_map = anagram_map.iter()
+            .filter(|(anagram_compact, _)| contains_chars_wild(&anagram_chars, &count_compact_chars(anagram_compact), options.wildcards))
+            .map(|(anagram_compact, words)| (expand_compact_key(anagram_compact), words.clone()))
+            .collect();
+        &expanded_map
+    } else {
+        anagram_map
+    };
+
+    let (mut anagram_search_lookups, mut filter_stats) = build_anagram_search_lookups(anagram_map, &anagram_chars, md5_checksums, options.min_word_len, options.wildcards);
+    // Count the words that didn't fit before expanding as excluded by the filter too.
+    filter_stats.excluded += anagram_map_len - filter_stats.total;
+    filter_stats.total = anagram_map_len;
+    anagram_search_lookups.word_scores = search_files.word_scores;
+
+    // After filtration, print the remaining anagrams to be searched
+    say!(&options.output_prefix, "Total: {}, Excluded: {}, Remaining: {}", filter_stats.total, filter_stats.excluded, filter_stats.remaining);
+
+    if let Some(top_words) = options.top_words {
+        let kept = limit_top_words(&mut anagram_search_lookups, &anagram_chars, top_words);
+        say!(&options.output_prefix, "Kept the top {} words by scarce letters, {} anagrams sorted", top_words, kept);
+    }
+
+    if let Some(max_bucket_words) = options.max_bucket_words {
+        let skipped = limit_bucket_words(&mut anagram_search_lookups, max_bucket_words);
+        say!(&options.output_prefix, "Kept at most {} words per sorted anagram, skipped {} words", max_bucket_words, skipped);
+    }
+
+    if !options.skip_roots.is_empty() || options.skip_roots_file.is_some() {
+        let removed = skip_roots(&mut anagram_search_lookups, &search_files.skipped_roots);
+        say!(&options.output_prefix, "Skipped {} of {} roots given", removed, search_files.skipped_roots.len());
+    }
+
+    if options.shuffle_roots {
+        let seed = options.seed.unwrap_or_else(|| std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64));
+        shuffle_roots(&mut anagram_search_lookups.anagrams_sorted_vec, seed);
+        say!(&options.output_prefix, "Shuffled the roots with seed: {}", seed);
+    }
+
+    let letter_suppliers = if options.smart_prune { count_letter_suppliers(&anagram_search_lookups) } else { HashMap::new() };
+    let anagram_search: AnagramSearch = AnagramSearch {
+        anagram_chars_search: anagram_chars,
+        wildcards_left: options.wildcards,
+        wildcard_letters: HashMap::new(),
+        options,
+        stop: Arc::new(AtomicBool::new(false)),
+        solved: Arc::new(Mutex::new(HashSet::new())),
+        unsolved: anagram_search_lookups.md5_checksums.clone(),
+        letter_suppliers,
+    };
+
+    search_anagram_phrases(anagram_search, anagram_search_lookups, None, filter_stats, load_time + filter_time.elapsed())
+}
+
+// Search the roots of an index read by read_index_roots for the checksums.
+// Only the roots are held for the whole search, the sorted anagrams each root
+// can be followed by are read from the index as it's searched.
+// Returns whether every checksum was solved.
+fn run_indexed_search(
+    mut index_roots: IndexRoots,
+    anagram_chars: HashMap<char, u32>,
+    md5_checksums: HashSet<Digest>,
+    search_files: SearchFiles,
+    options: AnagramOptions,
+    load_time: Duration) -> bool {
+
+    let filter_time = Instant::now();
+
+    if options.show_target_key {
+        print_target_key(&options.output_prefix, &anagram_chars);
+    }
+
+    let anagrams_sorted_chars = index_roots.roots.iter().map(|root| (root.clone(), count_chars(root))).collect();
+    let mut anagram_search_lookups = AnagramSearchLookups {
+        md5_checksums,
+        anagrams_sorted_vec: std::mem::take(&mut index_roots.roots),
+        anagrams_sorted_map: HashMap::new(),
+        anagrams_sorted_chars,
+        word_scores: search_files.word_scores,
+    };
+    let remaining = anagram_search_lookups.anagrams_sorted_vec.len();
+    let filter_stats = FilterStats { total: index_roots.lines, excluded: index_roots.lines - remaining, remaining };
+    say!(&options.output_prefix, "Total: {}, Excluded: {}, Remaining: {}", filter_stats.total, filter_stats.excluded, filter_stats.remaining);
+
+    if let Some(max_bucket_words) = options.max_bucket_words {
+        say!(&options.output_prefix, "Keeping at most {} words per sorted anagram as each root is read", max_bucket_words);
+    }
+
+    if !options.skip_roots.is_empty() || options.skip_roots_file.is_some() {
+        let removed = skip_roots(&mut anagram_search_lookups, &search_files.skipped_roots);
+        say!(&options.output_prefix, "Skipped {} of {} roots given", removed, search_files.skipped_roots.len());
+    }
+
+    let anagram_search: AnagramSearch = AnagramSearch {
+        anagram_chars_search: anagram_chars,
+        wildcards_left: options.wildcards,
+        wildcard_letters: HashMap::new(),
+        options,
+        stop: Arc::new(AtomicBool::new(false)),
+        solved: Arc::new(Mutex::new(HashSet::new())),
+        unsolved: anagram_search_lookups.md5_checksums.clone(),
+        letter_suppliers: HashMap::new(),
+    };
+
+    search_anagram_phrases(anagram_search, anagram_search_lookups, Some(&index_roots), filter_stats, load_time + filter_time.elapsed())
+}
+
+
+/// Loads the wordlist the options point at, ignoring the phrase of a combined
+/// file, so many phrases can be searched against it with search_anagram_map.
+/// Returns what's wrong if the wordlist can't be opened or read.
+pub fn load_anagram_map(options: &AnagramOptions) -> Result<HashMap<String, HashSet<String>>, String> {
+    let wordlist = match &options.combined {
+        Some(combined) => get_combined(combined)?.1,
+        None => open_wordlist(options.wordlist.as_deref())?,
+    };
+    get_anagram_map_loaded(wordlist, options)
+}
+
+/// What a wordlist holds once loaded, for curating it before searching.
+#[derive(Debug, PartialEq)]
+struct WordlistAnalysis {
+    words: usize, // Distinct words, duplicates in the wordlist are only loaded once
+    keys: usize, // Distinct sorted keys, each an anagram group
+    largest_group: Option<(String, Vec<String>)>, // The key the most words share and its words, sorted
+    word_lengths: BTreeMap<usize, usize>, // Letters in a word, spaces not counted -> how many words
+}
+
+fn analyze_anagram_map(anagram_map: &HashMap<String, HashSet<String>>) -> WordlistAnalysis {
+    let mut word_lengths: BTreeMap<usize, usize> = BTreeMap::new();
+    for word in anagram_map.values().flatten() {
+        *word_lengths.entry(word.chars().filter(|&c| c != ' ').count()).or_insert(0) += 1;
+    }
+
+    // The first key in sorted order wins a tie, so the report is the same every run.
+    let largest_group = anagram_map.iter()
+        .max_by(|a, b| a.1.len().cmp(&b.1.len()).then_with(|| b.0.cmp(a.0)))
+        .map(|(key, words)| {
+            let mut words: Vec<String> = words.iter().cloned().collect();
+            words.sort();
+            (key.clone(), words)
+        });
+
+    WordlistAnalysis {
+        words: anagram_map.values().map(HashSet::len).sum(),
+        keys: anagram_map.len(),
+        largest_group,
+        word_lengths,
+    }
+}
+
+/// Prints statistics about a wordlist loaded with load_anagram_map: how many
+/// words and sorted keys it has, its largest anagram group and how long its
+/// words are.
+pub fn print_wordlist_analysis(anagram_map: &HashMap<String, HashSet<String>>) {
+    let analysis = analyze_anagram_map(anagram_map);
+    println!("Words: {}, Sorted keys: {}",
+        analysis.words.to_formatted_string(&Locale::en),
+        analysis.keys.to_formatted_string(&Locale::en));
+    if let Some((key, words)) = &analysis.largest_group {
+        println!("Largest anagram group: {}, {} words: {}", key, words.len(), words.join(", "));
+    }
+    println!("Word lengths:");
+    for (length, count) in &analysis.word_lengths {
+        println!("{:>4} : {}", length, count.to_formatted_string(&Locale::en));
+    }
+}
+
+/// Searches a wordlist loaded with load_anagram_map for anagrams of the phrase.
+/// Returns whether every checksum was solved, or what's wrong if the options
+/// don't fit together or a file they name can't be read.
+pub fn search_anagram_map(
+    anagram_map: &HashMap<String, HashSet<String>>,
+    phrase: &str,
+    md5_checksums: HashSet<Digest>,
+    options: AnagramOptions) -> Result<bool, String> {
+
+    check_options(&options)?;
+    let (anagram, _) = parse_anagram(phrase);
+    let search_files = read_search_files(&options)?;
+    // The wordlist was loaded once up front, only the filtering counts toward this search.
+    Ok(run_anagram_search(anagram_map, count_chars(&anagram), md5_checksums, search_files, options, Duration::ZERO))
+}
+
+/// Checks that the options fit together, the checks every search makes
+/// before reading anything, whether it's built or run from --repl.
+pub fn check_options(options: &AnagramOptions) -> Result<(), String> {
+    if options.target.is_some() && options.combined.is_some() {
+        return Err("--target and --combined both give the anagram phrase, use one of them".to_string());
+    }
+    if options.wordlist.is_some() && options.combined.is_some() {
+        return Err("--wordlist and --combined both give the wordlist, use one of them".to_string());
+    }
+    if options.stream && options.compact_keys {
+        return Err("--stream only keeps the words that fit, there's nothing for --compact-keys to save".to_string());
+    }
+    if options.stream && options.parallel_load {
+        return Err("--stream and --parallel-load are different ways to load the wordlist, use one of them".to_string());
+    }
+    if options.max_bucket_words == Some(0) {
+        return Err("--max-bucket-words has to allow at least one word".to_string());
+    }
+    if options.max_depth == Some(0) {
+        return Err("--max-depth has to allow at least one word".to_string());
+    }
+    if options.jobs == Some(0) {
+        return Err("--jobs has to be at least 1".to_string());
+    }
+    if options.prefix_match > 32 {
+        return Err("--prefix-match can't be longer than the 32 hex characters of an MD5 digest".to_string());
+    }
+    if options.target_parallelism == 0 {
+        return Err("--target-parallelism has to be at least 1".to_string());
+    }
+    if options.target_parallelism != 1 && !options.repl {
+        return Err("--target-parallelism only applies to the targets read by --repl".to_string());
+    }
+    if options.target_parallelism > 1 && (options.metrics_out.is_some() || options.dot.is_some()) {
+        return Err("--metrics-out and --dot write one file, the targets --target-parallelism searches at once would all write it".to_string());
+    }
+    if options.word_scores.is_some() && options.top_phrases.is_none() {
+        return Err("--word-scores ranks the phrases kept with --top, give both".to_string());
+    }
+    if options.smart_prune && options.wildcards > 0 {
+        return Err("--smart-prune counts the words that can supply each letter, but a wildcard can supply any of them".to_string());
+    }
+    if options.smart_prune && options.subset {
+        return Err("--subset completes phrases with letters left over, --smart-prune would cut them short".to_string());
+    }
+    if options.top_phrases.is_some() && options.count_only {
+        return Err("--count-only doesn't build the phrases --top ranks, use one of them".to_string());
+    }
+    let phrase_filtered = !options.phrase_allow.is_empty() || !options.phrase_deny.is_empty()
+        || options.first_letter.is_some() || options.last_letter.is_some();
+    if phrase_filtered && options.count_only {
+        return Err("--count-only doesn't build the phrases --phrase-allow, --phrase-deny, --first-letter and --last-letter look at, use one of them".to_string());
+    }
+    if options.index.is_some() {
+        let conflicting = [
+            (options.wordlist.is_some() || options.combined.is_some(), "--wordlist and --combined give a wordlist to load, --index is searched in its place"),
+            (options.stream || options.parallel_load || options.compact_keys, "--stream, --parallel-load and --compact-keys load a wordlist, --index is read as it's searched"),
+            (options.dump_excluded.is_some(), "--dump-excluded lists the words of a loaded wordlist, --index only reads the ones that fit"),
+            (options.top_words.is_some() || options.shuffle_roots, "--top-words and --shuffle-roots need every root up front, --index reads them in its own order"),
+            (options.smart_prune, "--smart-prune counts the words of every root up front, --index only reads them a root at a time"),
+            (options.repl, "--repl loads a wordlist to search many targets, use --index without it"),
+            (options.write_index.is_some(), "--write-index writes an index from a wordlist, use it before searching with --index"),
+        ];
+        if let Some((_, err)) = conflicting.iter().find(|(conflicts, _)| *conflicts) {
+            return Err(err.to_string());
+        }
+    }
+    if options.write_index.is_some() && options.compact_keys {
+        return Err("--write-index writes sorted keys, there's nothing for --compact-keys to save".to_string());
+    }
+    if options.repl && !options.checksum_bin.is_empty() {
+        return Err("--checksum-bin isn't used by --repl, give the checksums after each phrase".to_string());
+    }
+    if options.repl && options.dump_excluded.is_some() {
+        return Err("--dump-excluded needs the target while loading, --repl loads the wordlist before reading any".to_string());
+    }
+    if options.repl && options.stream {
+        return Err("--stream filters the wordlist for the target while loading, --repl loads it before reading any".to_string());
+    }
+    Ok(())
+}
+
+/// Configures a search one setting at a time. Nothing is read until build,
+/// which checks the settings fit together first.
+///
+/// ```no_run
+/// use any_anagram::AnagramSearchBuilder;
+///
+/// let search = AnagramSearchBuilder::new()
+///     .wordlist("resources/wordlist")
+///     .target("poultry outwits ants")
+///     .add_checksum("e4820b45d2277f3844eac66c903e84be".parse().unwrap())
+///     .max_depth(3)
+///     .jobs(4)
+///     .build()
+///     .expect("Invalid search");
+/// let all_solved = search.run();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct AnagramSearchBuilder {
+    options: AnagramOptions, // Everything but the checksums
+    md5_checksums: HashSet<Digest>, // Checksums added one at a time
+}
+
+impl AnagramSearchBuilder {
+    pub fn new() -> Self {
+        AnagramSearchBuilder::default()
+    }
+
+    /// Starts from options read elsewhere, such as the command line.
+    /// Replaces every setting made so far except the checksums.
+    pub fn options(mut self, options: AnagramOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// The wordlist to search, resources/wordlist if not set.
+    pub fn wordlist(mut self, filename: &str) -> Self {
+        self.options.wordlist = Some(filename.to_string());
+        self
+    }
+
+    /// The phrase to find anagrams of, read from resources/anagram if not set.
+    pub fn target(mut self, phrase: &str) -> Self {
+        self.options.target = Some(phrase.to_string());
+        self
+    }
+
+    /// A checksum to search for, call it again for each one.
+    pub fn add_checksum(mut self, checksum: Digest) -> Self {
+        self.md5_checksums.insert(checksum);
+        self
+    }
+
+    /// The most words a phrase may have.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.options.max_depth = Some(max_depth);
+        self
+    }
+
+    /// How many root words are searched at once, the number of cores if not set.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.options.jobs = Some(jobs);
+        self
+    }
+
+    /// Checks the settings, then reads the target and loads the wordlist.
+    /// Returns what's wrong if the settings don't fit together or a file
+    /// can't be read.
+    pub fn build(self) -> Result<ConfiguredAnagramSearch, String> {
+        let load_time = Instant::now();
+        let options = self.options;
+        check_options(&options)?;
+        if options.repl {
+            return Err("--repl reads its targets from stdin, search them with search_anagram_map".to_string());
+        }
+
+        let mut md5_checksums = self.md5_checksums;
+        for filename in &options.checksum_bin {
+            md5_checksums.insert(get_checksum_bin(filename)?);
+        }
+        // Counting and printing every digest are useful without a checksum to find.
+        if md5_checksums.is_empty() && !options.count_only && !options.print_digests {
+            return Err("There are no checksums to search for".to_string());
+        }
+
+        let search_files = read_search_files(&options)?;
+        if let Some(index) = &options.index {
+            let (anagram, _) = match &options.target {
+                Some(target) => parse_anagram(target),
+                None => get_anagram()?,
+            };
+            let anagram_chars = count_chars(&anagram);
+            let index_roots = read_index_roots(index, &anagram_chars, options.min_word_len, options.wildcards)?;
+            return Ok(ConfiguredAnagramSearch {
+                anagram_map: HashMap::new(),
+                anagram_chars,
+                md5_checksums,
+                search_files,
+                streamed_skipped: None,
+                index_roots: Some(index_roots),
+                options,
+                load_time: load_time.elapsed(),
+            });
+        }
+
+        // Get the initial data and do some formatting
+        let ((anagram,_search_phrase), wordlist) = match (&options.target, &options.combined) {
+            (Some(target), _) => (parse_anagram(target), open_wordlist(options.wordlist.as_deref())?),
+            (_, Some(combined)) => get_combined(combined)?,
+            _ => (get_anagram()?, open_wordlist(options.wordlist.as_deref())?),
+        };
+        let anagram_chars : HashMap<char, u32> = count_chars(&anagram);
+
+        let dump_excluded = options.dump_excluded.as_deref().unwrap_or_default();
+        let mut excluded_out = match &options.dump_excluded {
+            Some(path) => Some(BufWriter::new(File::create(path).map_err(|err| excluded_error(path, err))?)),
+            None => None,
+        };
+
+        let mut streamed_skipped = None;
+        let anagram_map = if options.stream {
+            let excluded_out = excluded_out.as_mut().map(|out| (out as &mut dyn Write, dump_excluded));
+            let (anagram_map, skipped) = get_anagram_map_streamed(wordlist, wordlist_name(&options), &anagram_chars, options.wildcards, excluded_out)?;
+            streamed_skipped = Some(skipped);
+            anagram_map
+        } else {
+            let anagram_map = get_anagram_map_loaded(wordlist, &options)?;
+            if let Some(out) = excluded_out.as_mut() {
+                // The same comparison the filter makes, but keeping track of the words.
+                let mut excluded_words: Vec<(&String, HashMap<char, u32>)> = Vec::new();
+                for (anagram_sorted, words) in anagram_map.iter() {
+                    let anagram_sorted_chars = count_key_chars(anagram_sorted, options.compact_keys);
+                    if !contains_chars_wild(&anagram_chars, &anagram_sorted_chars, options.wildcards) {
+                        excluded_words.extend(words.iter().map(|word| (word, anagram_sorted_chars.clone())));
+                    }
+                }
+                excluded_words.sort_by(|a, b| a.0.cmp(b.0));
+                for (word, word_chars) in excluded_words {
+                    write_excluded_word(out, word, &anagram_chars, &word_chars).map_err(|err| excluded_error(dump_excluded, err))?;
+                }
+            }
+            anagram_map
+        };
+        if let Some(mut out) = excluded_out {
+            out.flush().map_err(|err| excluded_error(dump_excluded, err))?;
+        }
+
+        Ok(ConfiguredAnagramSearch {
+            anagram_map,
+            anagram_chars,
+            md5_checksums,
+            search_files,
+            streamed_skipped,
+            index_roots: None,
+            options,
+            load_time: load_time.elapsed(),
+        })
+    }
+}
+
+/// A search with its target read and wordlist loaded, ready to run.
+#[derive(Clone, Debug)]
+pub struct ConfiguredAnagramSearch {
+    anagram_map: HashMap<String, HashSet<String>>, // Sorted anagram -> Words, before filtering for the target
+    anagram_chars: HashMap<char, u32>, // The characters of the target
+    md5_checksums: HashSet<Digest>, // Checksums to search for
+    search_files: SearchFiles, // The word scores and roots to skip, read from their files
+    streamed_skipped: Option<u64>, // How many words --stream left out while loading
+    index_roots: Option<IndexRoots>, // The roots of --index, searched instead of the anagram map
+    options: AnagramOptions, // The options the search was built with
+    load_time: Duration, // How long reading the target and wordlist took
+}
+
+impl ConfiguredAnagramSearch {
+    /// Runs the search, returning whether every checksum was solved.
+    pub fn run(self) -> bool {
+        if let Some(skipped) = self.streamed_skipped {
+            println!("Streamed wordlist, skipped words: {}", skipped);
+        }
+        if let Some(index_roots) = self.index_roots {
+            return run_indexed_search(index_roots, self.anagram_chars, self.md5_checksums, self.search_files, self.options, self.load_time);
+        }
+        run_anagram_search(&self.anagram_map, self.anagram_chars, self.md5_checksums, self.search_files, self.options, self.load_time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A brute force solver that is slow but obviously correct. Every dictionary
+    // word is tried in every position, taking its letters out of what remains,
+    // until no letters are left, or with subset until no word fits.
+    fn reference_phrases(words: &[&str], anagram: &str) -> HashSet<String> {
+        reference_phrases_with(words, anagram, false)
+    }
+
+    fn reference_phrases_with(words: &[&str], anagram: &str, subset: bool) -> HashSet<String> {
+        fn solve(words: &[&str], remaining: &str, subset: bool, phrase: &mut Vec<String>, phrases: &mut HashSet<String>) {
+            let mut extended = false;
+            'words: for word in words {
+                let mut left: String = remaining.to_string();
+                for c in word.chars() {
+                    match left.find(c) {
+                        Some(position) => { left.remove(position); }
+                        None => continue 'words,
+                    }
+                }
+
+                extended = true;
+                phrase.push(word.to_string());
+                solve(words, &left, subset, phrase, phrases);
+                phrase.pop();
+            }
+
+            if !extended && (remaining.is_empty() || subset) && !phrase.is_empty() {
+                phrases.insert(phrase.join(" "));
+            }
+        }
+
+        let mut phrases = HashSet::new();
+        solve(words, &anagram.replace(' ', ""), subset, &mut Vec::new(), &mut phrases);
+        phrases
+    }
+
+    // The anagram map a wordlist of these words would load as.
+    fn anagram_map_of(words: &[&str]) -> HashMap<String, HashSet<String>> {
+        let mut anagram_map: HashMap<String, HashSet<String>> = HashMap::new();
+        for word in words {
+            anagram_map.entry(get_anagram_sorted(word)).or_default().insert(word.to_string());
+        }
+        anagram_map
+    }
+
+    // Runs every root through the same traversal the concurrent search uses,
+    // one at a time, and collects the metrics every root task reported.
+    fn fast_metrics(words: &[&str], anagram: &str, md5_checksums: HashSet<Digest>, options: AnagramOptions) -> Vec<AnagramMetrics> {
+        let anagram_map = anagram_map_of(words);
+        let anagram_chars = count_chars(&anagram.replace(' ', ""));
+        let (anagram_search_lookups, _) = build_anagram_search_lookups(&anagram_map, &anagram_chars, md5_checksums, options.min_word_len, options.wildcards);
+        let mut anagram_search = AnagramSearch {
+            anagram_chars_search: anagram_chars,
+            letter_suppliers: count_letter_suppliers(&anagram_search_lookups),
+            wildcards_left: options.wildcards,
+            wildcard_letters: HashMap::new(),
+            options,
+            stop: Arc::new(AtomicBool::new(false)),
+            solved: Arc::new(Mutex::new(HashSet::new())),
+            unsolved: anagram_search_lookups.md5_checksums.clone(),
+        };
+
+        let (tx, rx): (Sender<AnagramMetrics>, Receiver<AnagramMetrics>) = channel();
+        for (index, root) in anagram_search_lookups.anagrams_sorted_vec.iter().enumerate() {
+            let root_chars = &anagram_search_lookups.anagrams_sorted_chars[root];
+            if subtract_search_chars(&mut anagram_search, root_chars) {
+                task::block_on(async_traverse_anagram_phrases(
+                    anagram_search.clone(),
+                    anagram_search_lookups.clone(),
+                    root.clone(),
+                    index,
+                    tx.clone()));
+
+                add_search_chars(&mut anagram_search, root_chars);
+            }
+            remove_letter_suppliers(&mut anagram_search.letter_suppliers, root_chars, &HashMap::new());
+        }
+        drop(tx);
+
+        rx.iter().collect()
+    }
+
+    fn fast_phrases(words: &[&str], anagram: &str, md5_checksums: HashSet<Digest>) -> HashSet<String> {
+        fast_metrics(words, anagram, md5_checksums, AnagramOptions::default())
+            .into_iter()
+            .flat_map(|metrics| metrics.anagram_phrase_solution.into_keys())
+            .collect()
+    }
+
+    // Every phrase the reference finds is a checksum target, so the fast search
+    // has to report exactly the same set.
+    fn assert_matches_reference(words: &[&str], anagram: &str) {
+        let expected = reference_phrases(words, anagram);
+        assert!(!expected.is_empty(), "the reference found no phrases for {:?}", anagram);
+
+        let md5_checksums = expected.iter().map(|phrase| digest_of(phrase)).collect();
+        assert_eq!(fast_phrases(words, anagram, md5_checksums), expected);
+    }
+
+    #[test]
+    fn two_word_phrases_match_reference() {
+        assert_matches_reference(&["cat", "act", "dog", "god", "cog", "tad", "zzz"], "tac dog");
+    }
+
+    #[test]
+    fn phrases_of_every_length_match_reference() {
+        assert_matches_reference(&["a", "b", "c", "d", "ab", "cd", "abc", "bcd", "dcba"], "abcd");
+    }
+
+    #[test]
+    fn repeated_words_match_reference() {
+        assert_matches_reference(&["a", "b", "c", "ab", "ba", "bc", "aab"], "aabbc");
+    }
+
+    #[test]
+    fn six_word_phrases_match_reference() {
+        assert_matches_reference(&["a", "b", "c", "d", "e", "f", "fe", "cab"], "abc def");
+    }
+
+    #[test]
+    fn multi_word_entries_are_keyed_without_spaces() {
+        assert_eq!(get_anagram_sorted("new york"), "eknorwy");
+
+        let phrases = ["new york city", "city new york"];
+        let md5_checksums = phrases.iter().map(|phrase| digest_of(phrase)).collect();
+        let expected: HashSet<String> = phrases.iter().map(|phrase| phrase.to_string()).collect();
+        assert_eq!(fast_phrases(&["new york", "city"], "new york city", md5_checksums), expected);
+    }
+
+    #[test]
+    fn digest_round_trips_through_hex() {
+        let digest = digest_of("cat dog");
+        assert_eq!(digest.to_string(), "b89526a82f7ec08c202c2345fbd6aef3");
+        assert_eq!("b89526a82f7ec08c202c2345fbd6aef3".parse::<Digest>(), Ok(digest));
+        assert!("not hex".parse::<Digest>().is_err());
+        assert!("abcd".parse::<Digest>().is_err());
+        assert!("b89526a82f7ec08c202c2345fbd6aef300".parse::<Digest>().is_err());
+    }
+
+    #[test]
+    fn subset_phrases_match_reference() {
+        let words = ["a", "b", "c", "ab", "cab", "bad", "dd", "xyz"];
+        let expected = reference_phrases_with(&words, "abcxd", true);
+        assert!(expected.contains("a b c") && expected.contains("bad c"));
+
+        let md5_checksums = expected.iter().map(|phrase| digest_of(phrase)).collect();
+        let options = AnagramOptions { subset: true, ..AnagramOptions::default() };
+        let found: HashSet<String> = fast_metrics(&words, "abcxd", md5_checksums, options)
+            .into_iter()
+            .flat_map(|metrics| metrics.anagram_phrase_solution.into_keys())
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn count_only_matches_reference() {
+        let words = ["a", "b", "c", "ab", "ba", "bc", "aab"];
+        let options = AnagramOptions { count_only: true, ..AnagramOptions::default() };
+        let metrics = fast_metrics(&words, "aabbc", HashSet::new(), options);
+
+        let counted: u64 = metrics.iter().map(|metrics| metrics.anagram_phrases_counted).sum();
+        assert_eq!(counted, reference_phrases(&words, "aabbc").len() as u64);
+        assert!(metrics.iter().all(|metrics| metrics.anagram_phrases_found == 0));
+    }
+
+    #[test]
+    fn no_word_order_builds_each_set_once() {
+        let words = ["a", "b", "ab", "ba", "aab"];
+        let word_sets: HashSet<Vec<String>> = reference_phrases(&words, "aabb").iter()
+            .map(|phrase| {
+                let mut word_set: Vec<String> = phrase.split(' ').map(str::to_string).collect();
+                word_set.sort();
+                word_set
+            })
+            .collect();
+
+        let options = AnagramOptions { no_word_order: true, ..AnagramOptions::default() };
+        let found: u64 = fast_metrics(&words, "aabb", HashSet::new(), options.clone()).iter()
+            .map(|metrics| metrics.anagram_phrases_found)
+            .sum();
+        assert_eq!(found, word_sets.len() as u64);
+
+        let options = AnagramOptions { count_only: true, ..options };
+        let counted: u64 = fast_metrics(&words, "aabb", HashSet::new(), options).iter()
+            .map(|metrics| metrics.anagram_phrases_counted)
+            .sum();
+        assert_eq!(counted, word_sets.len() as u64);
+    }
+
+    #[test]
+    fn smart_prune_finds_every_phrase() {
+        let words = ["a", "b", "c", "d", "ab", "cd", "abc", "bcd", "dcba", "bb", "cc", "bc"];
+        let expected = reference_phrases(&words, "abcd");
+        let md5_checksums: HashSet<Digest> = expected.iter().map(|phrase| digest_of(phrase)).collect();
+
+        let options = AnagramOptions { smart_prune: true, ..AnagramOptions::default() };
+        let metrics = fast_metrics(&words, "abcd", md5_checksums, options);
+        let phrases: HashSet<String> = metrics.iter().flat_map(|metrics| metrics.anagram_phrase_solution.keys().cloned()).collect();
+        assert_eq!(phrases, expected);
+        assert!(metrics.iter().any(|metrics| metrics.anagram_branches_smart_pruned > 0));
+    }
+
+    #[test]
+    fn analyze_wordlist_summarizes_the_groups() {
+        let anagram_map = anagram_map_of(&["cat", "act", "tac", "dog", "god", "a", "new york"]);
+        let analysis = analyze_anagram_map(&anagram_map);
+        assert_eq!(analysis, WordlistAnalysis {
+            words: 7,
+            keys: 4,
+            largest_group: Some(("act".to_string(), vec!["act".to_string(), "cat".to_string(), "tac".to_string()])),
+            word_lengths: BTreeMap::from([(1, 1), (3, 5), (7, 1)]),
+        });
+    }
+
+    #[test]
+    fn uppercase_hash_matches_an_all_caps_checksum() {
+        assert_eq!(phrase_digest("New York", true), digest_of("NEW YORK"));
+        assert_eq!(phrase_digest("New York", false), digest_of("New York"));
+
+        let options = AnagramOptions { uppercase_hash: true, ..AnagramOptions::default() };
+        let metrics = fast_metrics(&["cat", "dog"], "tac god", HashSet::from([digest_of("CAT DOG")]), options);
+        let solution = metrics.iter().find_map(|metrics| metrics.anagram_phrase_solution.get("cat dog")).unwrap();
+        assert_eq!(solution.anagram_phrase_checksum, digest_of("CAT DOG"));
+    }
+
+    #[test]
+    fn max_bucket_words_keeps_the_first_words() {
+        let words = ["tac", "cat", "act", "dog", "god"];
+        let anagram_chars = count_chars("actdgo");
+        let (mut anagram_search_lookups, _) = build_anagram_search_lookups(&anagram_map_of(&words), &anagram_chars, HashSet::new(), 0, 0);
+        assert_eq!(limit_bucket_words(&mut anagram_search_lookups, 2), 1);
+        assert_eq!(anagram_search_lookups.anagrams_sorted_map["act"], vec!["act".to_string(), "cat".to_string()]);
+        assert_eq!(anagram_search_lookups.anagrams_sorted_map["dgo"].len(), 2);
+    }
+
+    #[test]
+    fn wildcards_are_given_back_in_reverse() {
+        let mut source = count_chars("aab");
+        let mut wildcards_left = 2;
+        let mut wildcard_letters: HashMap<char, u32> = HashMap::new();
+        let word_chars = [count_chars("abc"), count_chars("ab"), count_chars("cc")];
+
+        assert!(subtract_chars_wild(&mut source, &word_chars[0], &mut wildcards_left, &mut wildcard_letters));
+        assert!(subtract_chars_wild(&mut source, &word_chars[1], &mut wildcards_left, &mut wildcard_letters));
+        assert_eq!((source.clone(), wildcards_left), (HashMap::new(), 0));
+        assert_eq!(wildcard_letters, HashMap::from([('b', 1), ('c', 1)]));
+        assert!(!subtract_chars_wild(&mut source, &word_chars[2], &mut wildcards_left, &mut wildcard_letters));
+
+        add_chars_wild(&mut source, &word_chars[1], &mut wildcards_left, &mut wildcard_letters);
+        add_chars_wild(&mut source, &word_chars[0], &mut wildcards_left, &mut wildcard_letters);
+        assert_eq!((source, wildcards_left, wildcard_letters), (count_chars("aab"), 2, HashMap::new()));
+    }
+
+    #[test]
+    fn wildcards_stand_in_for_missing_letters() {
+        let options = AnagramOptions { wildcards: 1, ..AnagramOptions::default() };
+        let metrics = fast_metrics(&["cat", "dog", "cab"], "tac do", HashSet::from([digest_of("cat dog")]), options.clone());
+        let solution = metrics.iter().find_map(|metrics| metrics.anagram_phrase_solution.get("cat dog")).unwrap();
+        assert_eq!(solution.anagram_phrase_wildcards, "g");
+
+        // The wildcard has to be used, the phrase can't leave it over.
+        let metrics = fast_metrics(&["cat", "dog"], "tac dog", HashSet::from([digest_of("cat dog")]), options);
+        assert!(metrics.iter().all(|metrics| metrics.anagram_phrase_solution.is_empty()));
+    }
+
+    #[test]
+    fn dot_records_the_traversal() {
+        let options = AnagramOptions { dot: Some(String::new()), ..AnagramOptions::default() };
+        let metrics = fast_metrics(&["cat", "dog", "do"], "tac dog", HashSet::new(), options);
+        let mut dot_nodes: Vec<DotNode> = metrics.into_iter().filter(|metrics| metrics.is_done).flat_map(|metrics| metrics.anagram_dot_nodes).collect();
+        dot_nodes.sort();
+
+        let dot_node = |path: &[&str], kind| DotNode { path: path.iter().map(|key| key.to_string()).collect(), kind };
+        assert_eq!(dot_nodes, vec![
+            dot_node(&["act"], DotNodeKind::Fits),
+            dot_node(&["act", "act"], DotNodeKind::DoesntFit),
+            dot_node(&["act", "dgo"], DotNodeKind::Complete),
+            dot_node(&["act", "do"], DotNodeKind::Fits),
+            dot_node(&["act", "do", "do"], DotNodeKind::DoesntFit),
+            dot_node(&["dgo"], DotNodeKind::Fits),
+            dot_node(&["dgo", "dgo"], DotNodeKind::DoesntFit),
+            dot_node(&["dgo", "do"], DotNodeKind::DoesntFit),
+            dot_node(&["do"], DotNodeKind::Fits),
+            dot_node(&["do", "do"], DotNodeKind::DoesntFit),
+        ]);
+    }
+
+    #[test]
+    fn max_depth_leaves_out_longer_phrases() {
+        let words = ["a", "b", "c", "d", "ab", "cd", "abc", "bcd", "dcba"];
+        let expected: HashSet<String> = reference_phrases(&words, "abcd")
+            .into_iter()
+            .filter(|phrase| phrase.split(' ').count() <= 2)
+            .collect();
+
+        let md5_checksums = reference_phrases(&words, "abcd").iter().map(|phrase| digest_of(phrase)).collect();
+        let options = AnagramOptions { max_depth: Some(2), ..AnagramOptions::default() };
+        let found: HashSet<String> = fast_metrics(&words, "abcd", md5_checksums, options)
+            .into_iter()
+            .flat_map(|metrics| metrics.anagram_phrase_solution.into_keys())
+            .collect();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn top_phrases_keep_the_best_scores() {
+        let words = ["a", "b", "c", "d", "ab", "cd", "abc", "bcd", "dcba"];
+        let options = AnagramOptions { top_phrases: Some(2), ..AnagramOptions::default() };
+
+        let mut metrics = fast_metrics(&words, "abcd", HashSet::new(), options.clone());
+        let mut total = metrics.pop().unwrap();
+        for metrics in metrics {
+            add_metrics(&mut total, metrics, &options);
+        }
+
+        // Nothing has a score, so the fewest words win.
+        let top: Vec<String> = total.anagram_top_phrases.into_sorted_vec().into_iter().map(|Reverse(scored_phrase)| scored_phrase.phrase).collect();
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0], "dcba");
+        assert_eq!(top[1].split(' ').count(), 2);
+
+        let word_scores = HashMap::from([("cd".to_string(), 5), ("ab".to_string(), 1)]);
+        assert_eq!(score_phrase(&[&"ab".to_string(), &"cd".to_string()], &word_scores), 6);
+        let best = ScoredPhrase { score: 6, words: 2, phrase: "ab cd".to_string() };
+        let fewer_words = ScoredPhrase { score: 0, words: 1, phrase: "dcba".to_string() };
+        assert!(best > fewer_words);
+    }
+
+    #[test]
+    fn top_phrases_are_kept_once() {
+        // The two a's swap places in every order of "a a b", building each phrase twice.
+        let words = ["a", "b", "aa"];
+        let options = AnagramOptions { top_phrases: Some(10), ..AnagramOptions::default() };
+        let total = total_metrics(&words, "aab", HashSet::new(), options);
+
+        let top: Vec<String> = total.anagram_top_phrases.into_sorted_vec().into_iter().map(|Reverse(scored_phrase)| scored_phrase.phrase).collect();
+        let distinct: HashSet<&String> = top.iter().collect();
+        assert_eq!(distinct.len(), top.len());
+        assert_eq!(distinct, reference_phrases(&words, "aab").iter().collect());
+    }
+
+    // The metrics of a whole search, every root task's added up the way the search does.
+    fn total_metrics(words: &[&str], anagram: &str, md5_checksums: HashSet<Digest>, options: AnagramOptions) -> AnagramMetrics {
+        fast_metrics(words, anagram, md5_checksums, options.clone())
+            .into_iter()
+            .reduce(|mut total, metrics| {
+                add_metrics(&mut total, metrics, &options);
+                total
+            })
+            .expect("no root was searched")
+    }
+
+    #[test]
+    fn solutions_sort_by_time_found() {
+        let phrases = ["god cat", "cat dog", "act god"];
+        let md5_checksums = phrases.iter().map(|phrase| digest_of(phrase)).collect();
+        let mut metrics = total_metrics(&["cat", "act", "dog", "god"], "tac dog", md5_checksums, AnagramOptions::default());
+
+        // Pretend they were found in another order than the traversal's.
+        let start_time = Instant::now();
+        for (millis, phrase) in [(3, "god cat"), (1, "cat dog"), (2, "act god")] {
+            metrics.anagram_phrase_solution.get_mut(phrase).unwrap().anagram_phrase_time = start_time + Duration::from_millis(millis);
+        }
+        let in_order: Vec<&str> = solutions_in_order(&metrics, true).into_iter().map(|(phrase, _)| phrase.as_str()).collect();
+        assert_eq!(in_order, ["cat dog", "act god", "god cat"]);
+        assert_eq!(solutions_in_order(&metrics, false).len(), 3);
+    }
+
+    #[test]
+    fn checksums_are_solved_by_digest() {
+        let md5_checksums = HashSet::from([digest_of("cat dog"), digest_of("not a phrase")]);
+        let mut metrics = total_metrics(&["cat", "dog"], "tac dog", md5_checksums.clone(), AnagramOptions::default());
+        // A second phrase for the same checksum, like another spelling with --uppercase-hash.
+        let solution_metrics = metrics.anagram_phrase_solution["cat dog"].clone();
+        metrics.anagram_phrase_solution.insert("Cat dog".to_string(), solution_metrics);
+        assert!(!all_checksums_solved(&metrics, &md5_checksums));
+        assert!(all_checksums_solved(&metrics, &HashSet::from([digest_of("cat dog")])));
+        assert!(!all_checksums_solved(&metrics, &HashSet::new()));
+
+        // Counting without checksums has to get through every root instead of
+        // stopping before the first one.
+        let options = AnagramOptions { count_only: true, jobs: Some(1), ..AnagramOptions::default() };
+        assert!(!search_with_timeout(&["cat", "act", "dog", "god"], "tac dog", HashSet::new(), options));
+    }
+
+    #[test]
+    fn repl_searches_check_the_options() {
+        let anagram_map = anagram_map_of(&["cat", "dog"]);
+        let md5_checksums = HashSet::from([digest_of("cat dog")]);
+        for options in [
+            AnagramOptions { repl: true, smart_prune: true, subset: true, ..AnagramOptions::default() },
+            AnagramOptions { repl: true, jobs: Some(0), ..AnagramOptions::default() },
+            AnagramOptions { repl: true, stream: true, ..AnagramOptions::default() },
+        ] {
+            assert!(search_anagram_map(&anagram_map, "tac dog", md5_checksums.clone(), options).is_err());
+        }
+
+        let options = AnagramOptions { repl: true, target_parallelism: 2, jobs: Some(1), ..AnagramOptions::default() };
+        assert_eq!(search_anagram_map(&anagram_map, "tac dog", md5_checksums, options), Ok(true));
+    }
+
+    #[test]
+    fn phrases_are_filtered_by_pattern() {
+        let options = AnagramOptions {
+            phrase_allow: vec![Regex::new("^a").unwrap(), Regex::new("g$").unwrap()],
+            phrase_deny: vec![Regex::new("god").unwrap()],
+            ..AnagramOptions::default()
+        };
+        assert!(phrase_allowed("act dog", &options));
+        assert!(!phrase_allowed("act god", &options));
+        assert!(!phrase_allowed("cat dog", &options));
+        assert!(!phrase_allowed("act dot", &options));
+
+        let phrases = ["act dog", "cat dog", "act god", "cat god", "dog act", "god cat"];
+        let md5_checksums = phrases.iter().map(|phrase| digest_of(phrase)).collect();
+        let mut found: Vec<String> = total_metrics(&["cat", "act", "dog", "god"], "tac dog", md5_checksums, options.clone())
+            .anagram_phrase_solution.into_keys().collect();
+        found.sort();
+        assert_eq!(found, ["act dog"]);
+
+        // Counting never builds the phrases to match them against.
+        assert!(check_options(&AnagramOptions { count_only: true, ..options }).is_err());
+        assert!(check_options(&AnagramOptions { count_only: true, last_letter: Some('g'), ..AnagramOptions::default() }).is_err());
+    }
+
+    #[test]
+    fn metrics_report_has_every_key() {
+        let md5_checksums = HashSet::from([digest_of("cat dog")]);
+        let metrics = total_metrics(&["cat", "dog"], "tac dog", md5_checksums, AnagramOptions::default());
+        let filter_stats = FilterStats { total: 3, excluded: 1, remaining: 2 };
+        let report = serde_json::to_value(metrics_report(&metrics, filter_stats, Instant::now(), Duration::ZERO, true)).unwrap();
+
+        let mut keys: Vec<&String> = report.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(keys, [
+            "all_solved", "branches_pruned", "branches_smart_pruned", "filter", "load_seconds", "phrase_max_depth",
+            "phrases_counted", "phrases_found", "phrases_incomplete", "phrases_per_second",
+            "recursion_max_depth", "roots_exhausted", "search_seconds", "solutions",
+        ]);
+        assert_eq!(report["all_solved"], true);
+        assert_eq!(report["roots_exhausted"], 2);
+        assert_eq!(report["filter"], serde_json::json!({ "total": 3, "excluded": 1, "remaining": 2 }));
+
+        let solution = &report["solutions"][0];
+        let mut keys: Vec<&String> = solution.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(keys, ["digest", "phrase", "seconds_to_find", "wildcards"]);
+        assert_eq!(solution["phrase"], "cat dog");
+        assert_eq!(solution["digest"], "b89526a82f7ec08c202c2345fbd6aef3");
+    }
+
+    // Runs the search with --metrics-out and reads back whether the file says every checksum was solved.
+    fn metrics_out_all_solved(name: &str, words: &[&str], md5_checksums: HashSet<Digest>, options: AnagramOptions) -> bool {
+        let path = std::env::temp_dir().join(format!("any_anagram_{}_{}.json", std::process::id(), name));
+        let options = AnagramOptions { metrics_out: Some(path.to_string_lossy().into_owned()), ..options };
+        let all_solved = search_with_timeout(words, "tac dog", md5_checksums, options);
+
+        let report: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(report["all_solved"], all_solved);
+        all_solved
+    }
+
+    #[test]
+    fn metrics_out_says_whether_every_checksum_was_solved() {
+        let words = ["cat", "act", "dog", "god"];
+        let solvable = HashSet::from([digest_of("cat dog")]);
+        // One job fills up with the first root, so its solution ends the search in the loop spawning the roots.
+        assert!(metrics_out_all_solved("spawned", &words, solvable.clone(), AnagramOptions { jobs: Some(1), ..AnagramOptions::default() }));
+        // More jobs than roots leaves the solution for the loop draining the tasks.
+        assert!(metrics_out_all_solved("drained", &words, solvable.clone(), AnagramOptions { jobs: Some(8), ..AnagramOptions::default() }));
+        // Every root is searched when a checksum can't be solved, or to find every match.
+        let unsolvable = HashSet::from([digest_of("cat dog"), digest_of("not a phrase")]);
+        assert!(!metrics_out_all_solved("exhausted", &words, unsolvable, AnagramOptions::default()));
+        assert!(metrics_out_all_solved("all_matches", &words, solvable, AnagramOptions { all_matches: true, ..AnagramOptions::default() }));
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("cat dog"), "cat dog");
+        assert_eq!(csv_field("cat, dog"), "\"cat, dog\"");
+        assert_eq!(csv_field("the \"cat\""), "\"the \"\"cat\"\"\"");
+    }
+
+    #[test]
+    fn wordlists_that_cant_be_read_are_errors() {
+        // A latin-1 word isn't UTF-8, so its line can't be read.
+        let wordlist = || -> Wordlist { Box::new(&b"cat\ncaf\xe9\n"[..]) };
+        assert!(get_anagram_map(wordlist(), false).is_err());
+        assert!(get_anagram_map_parallel(wordlist(), false).is_err());
+        let streamed = get_anagram_map_streamed(wordlist(), "latin1", &count_chars("tac"), 0, None);
+        assert!(streamed.unwrap_err().starts_with("Could not read latin1: "));
+
+        assert_eq!(get_anagram_map(Box::new(&b"cat\nact\n"[..]), false).unwrap(), anagram_map_of(&["cat", "act"]));
+    }
+
+    #[test]
+    fn anagram_file_must_be_one_line() {
+        assert_eq!(read_anagram("\n tac dog \n\n".as_bytes()), Ok(("acdgot".to_string(), "tac dog".to_string())));
+        assert!(read_anagram("tac\ndog\n".as_bytes()).is_err());
+        assert!(read_anagram("\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn digest_prefixes_match_by_hex_character() {
+        let digest: Digest = "b89526a82f7ec08c202c2345fbd6aef3".parse().unwrap();
+        let close: Digest = "b89f00000000000000000000000000ff".parse().unwrap();
+        assert!(digest.prefix_matches(&close, 3));
+        assert!(!digest.prefix_matches(&close, 4));
+        assert!(digest.prefix_matches(&digest, 32));
+    }
+
+    #[test]
+    fn top_words_keeps_the_scarce_letters() {
+        let words = ["z", "za", "a", "b", "ab", "ba"];
+        let mut anagram_map: HashMap<String, HashSet<String>> = HashMap::new();
+        for word in words {
+            anagram_map.entry(get_anagram_sorted(word)).or_default().insert(word.to_string());
+        }
+
+        let anagram_chars = count_chars("zab");
+        let (mut anagram_search_lookups, _) = build_anagram_search_lookups(&anagram_map, &anagram_chars, HashSet::new(), 0, 0);
+        assert_eq!(limit_top_words(&mut anagram_search_lookups, &anagram_chars, 3), 2);
+
+        // Only two sorted anagrams have a z and two have a b, so "za" and the
+        // "ab" words make up more of the scarce letters than the single letters.
+        let mut kept: Vec<&String> = anagram_search_lookups.anagrams_sorted_map.values().flatten().collect();
+        kept.sort();
+        assert_eq!(kept, ["ab", "ba", "za"]);
+        assert_eq!(anagram_search_lookups.anagrams_sorted_vec.len(), 2);
+    }
+
+    // Runs the whole concurrent search on its own thread, failing instead of
+    // hanging the tests if it never finishes.
+    fn search_with_timeout(words: &[&str], anagram: &str, md5_checksums: HashSet<Digest>, options: AnagramOptions) -> bool {
+        let anagram_map = anagram_map_of(words);
+        let anagram_chars = count_chars(&anagram.replace(' ', ""));
+
+        let (done_tx, done_rx) = channel();
+        std::thread::spawn(move || {
+            let search_files = read_search_files(&options).unwrap();
+            done_tx.send(run_anagram_search(&anagram_map, anagram_chars, md5_checksums, search_files, options, Duration::ZERO)).ok();
+        });
+        done_rx.recv_timeout(Duration::from_secs(10)).expect("the search never finished")
+    }
+
+    #[test]
+    fn every_task_is_drained_with_more_roots_than_jobs() {
+        let words = ["a", "b", "c", "d", "ab", "cd", "abc", "bcd", "dcba"];
+        let options = AnagramOptions { jobs: Some(2), ..AnagramOptions::default() };
+        let md5_checksums = HashSet::from([digest_of("not a phrase")]);
+        assert!(!search_with_timeout(&words, "abcd", md5_checksums, options.clone()));
+
+        let md5_checksums = reference_phrases(&words, "abcd").iter().map(|phrase| digest_of(phrase)).collect();
+        assert!(search_with_timeout(&words, "abcd", md5_checksums, options));
+    }
+
+    #[test]
+    fn search_ends_with_fewer_roots_than_jobs() {
+        // Only two roots, so the spawn loop never fills the jobs and every
+        // message is left for the loop draining the running tasks.
+        let words = ["cat", "dog"];
+        let options = AnagramOptions { jobs: Some(8), ..AnagramOptions::default() };
+        let md5_checksums = HashSet::from([digest_of("not a phrase")]);
+        assert!(!search_with_timeout(&words, "tac dog", md5_checksums, options.clone()));
+
+        let md5_checksums = HashSet::from([digest_of("cat dog"), digest_of("dog cat")]);
+        assert!(search_with_timeout(&words, "tac dog", md5_checksums, options));
+    }
+
+    fn index_of(name: &str, words: &[&str]) -> String {
+        let path = std::env::temp_dir().join(format!("any_anagram_{}_{}.index", std::process::id(), name));
+        let path = path.to_string_lossy().into_owned();
+        write_index(&anagram_map_of(words), &path).unwrap();
+        path
+    }
+
+    fn indexed_search_with_timeout(index: &str, anagram: &str, md5_checksums: HashSet<Digest>, options: AnagramOptions) -> bool {
+        let anagram_chars = count_chars(&anagram.replace(' ', ""));
+        let index_roots = read_index_roots(index, &anagram_chars, options.min_word_len, options.wildcards).unwrap();
+
+        let (done_tx, done_rx) = channel();
+        std::thread::spawn(move || {
+            let search_files = read_search_files(&options).unwrap();
+            done_tx.send(run_indexed_search(index_roots, anagram_chars, md5_checksums, search_files, options, Duration::ZERO)).ok();
+        });
+        done_rx.recv_timeout(Duration::from_secs(10)).expect("the search never finished")
+    }
+
+    #[test]
+    fn indexed_search_finds_what_a_loaded_search_does() {
+        let words = ["a", "b", "c", "d", "ab", "cd", "abc", "bcd", "dcba", "zebra"];
+        let index = index_of("search", &words);
+        let anagram_chars = count_chars("abcd");
+        let index_roots = read_index_roots(&index, &anagram_chars, 0, 0).unwrap();
+        assert_eq!(index_roots.roots, ["abcd", "abc", "bcd", "ab", "cd", "a", "b", "c", "d"]);
+        assert_eq!(index_roots.lines, 10);
+
+        let options = AnagramOptions { all_matches: true, jobs: Some(2), ..AnagramOptions::default() };
+        let md5_checksums: HashSet<Digest> = reference_phrases(&words, "abcd").iter().map(|phrase| digest_of(phrase)).collect();
+        assert!(indexed_search_with_timeout(&index, "abcd", md5_checksums.clone(), options.clone()));
+        assert!(search_with_timeout(&words, "abcd", md5_checksums.clone(), options.clone()));
+
+        let md5_checksums = HashSet::from([digest_of("not a phrase")]);
+        assert!(!indexed_search_with_timeout(&index, "abcd", md5_checksums, options));
+        std::fs::remove_file(&index).ok();
+    }
+
+    #[test]
+    fn index_must_be_in_search_order() {
+        let path = std::env::temp_dir().join(format!("any_anagram_{}_misordered.index", std::process::id()));
+        std::fs::write(&path, "act\tcat\ndgo\tdog\nabcd\tdcba\n").unwrap();
+        let index_roots = read_index_roots(&path.to_string_lossy(), &count_chars("tacdog"), 0, 0);
+        std::fs::remove_file(&path).ok();
+        assert!(index_roots.unwrap_err().contains("line 3"));
+    }
+
+    // Runs every root of an index through the traversal one at a time, like
+    // fast_metrics does for a loaded wordlist.
+    fn indexed_metrics(index: &str, anagram: &str, md5_checksums: HashSet<Digest>, options: AnagramOptions) -> Vec<AnagramMetrics> {
+        let anagram_chars = count_chars(&anagram.replace(' ', ""));
+        let index_roots = read_index_roots(index, &anagram_chars, options.min_word_len, options.wildcards).unwrap();
+        let anagram_search_lookups = AnagramSearchLookups {
+            md5_checksums,
+            anagrams_sorted_chars: index_roots.roots.iter().map(|root| (root.clone(), count_chars(root))).collect(),
+            anagrams_sorted_vec: index_roots.roots.clone(),
+            anagrams_sorted_map: HashMap::new(),
+            word_scores: HashMap::new(),
+        };
+        let mut anagram_search = AnagramSearch {
+            anagram_chars_search: anagram_chars,
+            letter_suppliers: HashMap::new(),
+            wildcards_left: options.wildcards,
+            wildcard_letters: HashMap::new(),
+            options,
+            stop: Arc::new(AtomicBool::new(false)),
+            solved: Arc::new(Mutex::new(HashSet::new())),
+            unsolved: anagram_search_lookups.md5_checksums.clone(),
+        };
+
+        let (tx, rx): (Sender<AnagramMetrics>, Receiver<AnagramMetrics>) = channel();
+        for (index, root) in anagram_search_lookups.anagrams_sorted_vec.iter().enumerate() {
+            let root_chars = &anagram_search_lookups.anagrams_sorted_chars[root];
+            if subtract_search_chars(&mut anagram_search, root_chars) {
+                let (root_lookups, resume_index) = index_roots.root_lookups(index, &anagram_search, &anagram_search_lookups);
+                task::block_on(async_traverse_anagram_phrases(anagram_search.clone(), root_lookups, root.clone(), resume_index, tx.clone()));
+                add_search_chars(&mut anagram_search, root_chars);
+            }
+        }
+        drop(tx);
+
+        rx.iter().collect()
+    }
+
+    #[test]
+    fn indexed_subset_phrases_match_reference() {
+        for (words, anagram) in [(&["ab", "c"][..], "abcx"), (&["a", "b", "c", "ab", "cab", "bad", "dd", "xyz"][..], "abcxd")] {
+            let index = index_of("subset", words);
+            let expected = reference_phrases_with(words, anagram, true);
+            // Phrases a word still fits in aren't complete, so they mustn't be found either.
+            let md5_checksums = expected.iter().chain(&["c".to_string(), "a b".to_string(), "ab".to_string()])
+                .map(|phrase| digest_of(phrase))
+                .collect();
+            let options = AnagramOptions { subset: true, all_matches: true, ..AnagramOptions::default() };
+            let found: HashSet<String> = indexed_metrics(&index, anagram, md5_checksums, options)
+                .into_iter()
+                .flat_map(|metrics| metrics.anagram_phrase_solution.into_keys())
+                .collect();
+            std::fs::remove_file(&index).ok();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn compact_keys_round_trip() {
+        assert_eq!(get_anagram_compact("banana"), Some("a3bn2".to_string()));
+        assert_eq!(get_anagram_compact("new york"), Some("eknorwy".to_string()));
+        assert_eq!(get_anagram_compact("r2d2"), None);
+        assert_eq!(get_anagram_compact(&"a".repeat(12)), Some("a12".to_string()));
+
+        for word in ["banana", "mississippi", "a", "abc"] {
+            let anagram_compact = get_anagram_compact(word).unwrap();
+            assert_eq!(expand_compact_key(&anagram_compact), get_anagram_sorted(word));
+            assert_eq!(count_compact_chars(&anagram_compact), count_chars(word));
+        }
+    }
+
+    #[test]
+    fn all_matches_searches_every_root() {
+        let words = ["cat", "act", "dog", "god"];
+        let options = AnagramOptions { all_matches: true, jobs: Some(2), ..AnagramOptions::default() };
+        let md5_checksums = HashSet::from([digest_of("cat dog"), digest_of("god act")]);
+        assert!(search_with_timeout(&words, "tac dog", md5_checksums, options.clone()));
+
+        let md5_checksums = HashSet::from([digest_of("cat dog"), digest_of("not a phrase")]);
+        assert!(!search_with_timeout(&words, "tac dog", md5_checksums, options));
+    }
+
+    #[test]
+    fn shuffled_roots_still_find_every_phrase() {
+        let mut roots: Vec<String> = (0..20).map(|root| root.to_string()).collect();
+        let mut again = roots.clone();
+        shuffle_roots(&mut roots, 7);
+        shuffle_roots(&mut again, 7);
+        assert_eq!(roots, again);
+        assert_ne!(roots, (0..20).map(|root| root.to_string()).collect::<Vec<String>>());
+
+        let words = ["a", "b", "c", "d", "ab", "cd", "abc", "bcd", "dcba"];
+        let md5_checksums = reference_phrases(&words, "abcd").iter().map(|phrase| digest_of(phrase)).collect();
+        let options = AnagramOptions { shuffle_roots: true, seed: Some(7), ..AnagramOptions::default() };
+        assert!(search_with_timeout(&words, "abcd", md5_checksums, options));
+    }
+
+    #[test]
+    fn skipped_roots_are_left_out() {
+        let words = ["cat", "act", "dog", "god", "odg", "zebra"];
+        let anagram_chars = count_chars("actdgo");
+        let anagram_map = anagram_map_of(&words);
+        let (mut anagram_search_lookups, filter_stats) = build_anagram_search_lookups(&anagram_map, &anagram_chars, HashSet::new(), 0, 0);
+        assert_eq!(filter_stats, FilterStats { total: 3, excluded: 1, remaining: 2 });
+        let skipped = HashSet::from([get_anagram_sorted("god"), get_anagram_sorted("xyz")]);
+        assert_eq!(skip_roots(&mut anagram_search_lookups, &skipped), 1);
+        assert_eq!(anagram_search_lookups.anagrams_sorted_vec, vec!["act".to_string()]);
+
+        let options = AnagramOptions { skip_roots: vec!["dog".to_string()], ..AnagramOptions::default() };
+        assert!(!search_with_timeout(&words, "tac dog", HashSet::from([digest_of("cat dog")]), options));
+    }
+}
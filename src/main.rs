@@ -2,542 +2,455 @@
 /// My solution to the Trust Pilot anagram phrase problem.
 /// The fastest way to learn something new is to dive into it and I used this
 /// problem as a means to learn Rust.
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, Write};
 use std::collections::{HashMap, HashSet};
-use std::ops::Index;
-use async_std::task;
-use num_cpus;
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::time::Instant;
 use std::process;
-use std::cmp::Ordering;
+use regex::Regex;
+use any_anagram::{check_options, load_anagram_map, print_wordlist_analysis, search_anagram_map, write_index, AnagramOptions, AnagramSearchBuilder, Digest, OutputFormat, EXIT_ERROR};
+
+const USAGE: &str = "\
+Usage: any_anagram [OPTIONS]
+
+Options:
+    --target <phrase>  Search for anagrams of the phrase instead of reading resources/anagram
+    --combined <path>  Read the anagram phrase from the first line of the file and the
+                       wordlist from the rest, instead of the files in resources/
+    --wordlist <path>  Search the wordlist instead of resources/wordlist, may be an http(s):// URL
+                       when built with the net feature
+    --checksum-bin <path>
+                       Search for the raw 16 byte MD5 digest in the file instead of the
+                       built in checksums, may be repeated
+    --uppercase-hash   Hash each phrase uppercased, for checksums made from an all caps phrase. The
+                       phrase is still printed as it is in the wordlist
+    --print-digests    Print \"phrase : digest\" for every completed phrase. Without --checksum-bin
+                       or ANAGRAM_CHECKSUMS no checksums are searched for, only the digests printed
+    --prefix-match <n> Print a near miss for every phrase whose digest starts with the same n hex
+                       characters as a checksum without matching it
+    --stream           Filter the wordlist while reading it instead of loading all of it first
+    --write-index <path>
+                       Write the wordlist as an index for --index, its sorted keys in search order
+                       with their words, then exit without searching
+    --index <path>     Search an index written by --write-index instead of loading a wordlist. Only
+                       the roots that fit are kept, each root's words are read from the index as
+                       it's searched, for wordlists too large to load at the cost of some speed
+    --parallel-load    Sort the wordlist's words on every core, worth it for large wordlists
+    --compact-keys     Key the loaded wordlist by letter counts, EG: a3bn2 for banana, instead of
+                       sorted letters. Words with digits are left out
+    --no-word-order    Build each set of words once instead of in every order. Only finds a checksum
+                       of the one order built, so only use it when the order doesn't matter
+    --count-only       Count the valid phrases without building or hashing them
+    --stream-solutions Print each solution as soon as it's found
+    --sort-by-time     Print the solutions at the end in the order they were found
+    --all-matches      Keep looking for phrases matching a checksum after it's solved, searching
+                       every root, then print the phrases found for each checksum
+    --output-format <text|csv>
+                       How to print the solutions when the search ends, csv prints a
+                       phrase,digest,duration_ms header and a row per solution last
+    --dump-excluded <path>
+                       Write every word the character filter excluded, with the offending characters
+    --phrase-allow <regex>
+                       Only consider phrases matching the pattern, may be repeated
+    --phrase-deny <regex>
+                       Never consider phrases matching the pattern, may be repeated
+    --wildcards <n>    The target also has n blank letters, each standing in for any letter a word
+                       needs, and every one of them has to be used. The letters they stood in for
+                       are printed with each solution
+    --subset           Also find phrases that don't use every letter, as long as no word fits
+                       in the letters left over
+    --min-word-len <n> Leave out words shorter than n letters
+    --top-words <n>    Only search the n words with the target's scarcest letters. Faster on huge
+                       wordlists, but solutions using the words left out will be missed
+    --max-bucket-words <n>
+                       Only try the first n words, in sorted order, of words that are anagrams of
+                       each other. Faster when many words share their letters, but solutions
+                       using the words left out will be missed
+    --min-phrase-len <n>
+                       Leave out phrases with fewer than n letters, not counting spaces
+    --smart-prune      Give up on a branch once a letter it still needs is in none of the words
+                       left to try. The branches cut short are counted at the end, compare the
+                       search time too, the bookkeeping can cost more than the pruning saves
+    --max-recursion <depth>
+                       Give up on any branch of the search deeper than this
+    --max-depth <n>    Leave out phrases with more than n words
+    --top <k>          Print the k best scoring phrases at the end, fewer words scoring higher on a tie
+    --word-scores <path>
+                       Score each phrase by adding up its words' scores, read from \"word score\" lines
+    --jobs <n>         Search n root words at once instead of one per core
+    --shuffle-roots    Search the roots in a random order instead of longest first, to sample the
+                       whole search rather than finishing the long roots first. Loses the
+                       pruning the longest first order gives, so exhausting the search is slower
+    --seed <n>         The seed for --shuffle-roots, printed when it's picked from the clock
+    --skip-root <key>  Leave the sorted letters out of the search, EG: dgo for dog, may be repeated
+    --skip-roots-file <path>
+                       Leave out every sorted key in the file, one per line
+    --show-target-key  Print the target's sorted letters and the count of each before searching
+    --metrics-out <path>
+                       Write the final metrics and solutions to the file as JSON
+    --dot <path>       Write the search tree to the file as a Graphviz DOT graph, for small wordlists.
+                       Only the first 1000 steps are written
+    --profile          Time the letter arithmetic, permutation and hashing and print the share of
+                       the search each took
+    --verbose          Log every word the search tries, whether it fits and when it backtracks,
+                       to stderr. Meant for tiny wordlists, it's a lot of output
+    --analyze-wordlist Load the wordlist and print how many words and sorted keys it has, its
+                       largest anagram group and its word lengths, then exit without searching
+    --repl             Load the wordlist once then read \"phrase | checksum ...\" lines from stdin,
+                       searching each one until \"quit\"
+    --target-parallelism <n>
+                       With --repl, search n lines at once, splitting the jobs between them.
+                       Each line printed for one starts with its phrase in brackets
+    --first-letter <c> Only consider phrases whose first word starts with the letter
+    --last-letter <c>  Only consider phrases whose last word ends with the letter
+    --no-solutions-exit-code <code>
+                       Exit code when some checksums weren't solved, defaults to 1
+    -h, --help         Print this help
+
+Environment:
+    ANAGRAM_TARGET     The anagram phrase when neither --target nor --combined gives it
+    ANAGRAM_WORDLIST   The wordlist when none of --wordlist, --combined or --index is given
+    ANAGRAM_CHECKSUMS  Comma separated hex digests to search for instead of the built in checksums
+                       when no --checksum-bin is given, not used by --repl
+
+An option always wins over the environment, which wins over the files in resources/.
+
+Exits with 0 when every checksum was solved and 2 on errors such as a missing file.";
+
+/// Reads the command line options, exiting with the usage text on anything
+/// that isn't recognized. Returns whether --verbose was given apart from the
+/// search's options, logging is set up here rather than by the library.
+fn get_options() -> (AnagramOptions, bool) {
+    let mut options = AnagramOptions::default();
+    let mut verbose = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--target" => options.target = Some(get_option_value(&arg, args.next())),
+            "--combined" => options.combined = Some(get_option_value(&arg, args.next())),
+            "--wordlist" => options.wordlist = Some(get_option_value(&arg, args.next())),
+            "--checksum-bin" => options.checksum_bin.push(get_option_value(&arg, args.next())),
+            "--uppercase-hash" => options.uppercase_hash = true,
+            "--print-digests" => options.print_digests = true,
+            "--prefix-match" => options.prefix_match = get_option_number(&arg, args.next()),
+            "--stream" => options.stream = true,
+            "--index" => options.index = Some(get_option_value(&arg, args.next())),
+            "--write-index" => options.write_index = Some(get_option_value(&arg, args.next())),
+            "--parallel-load" => options.parallel_load = true,
+            "--compact-keys" => options.compact_keys = true,
+            "--analyze-wordlist" => options.analyze_wordlist = true,
+            "--repl" => options.repl = true,
+            "--verbose" => verbose = true,
+            "--dot" => options.dot = Some(get_option_value(&arg, args.next())),
+            "--profile" => options.profile = true,
+            "--metrics-out" => options.metrics_out = Some(get_option_value(&arg, args.next())),
+            "--show-target-key" => options.show_target_key = true,
+            "--no-word-order" => options.no_word_order = true,
+            "--count-only" => options.count_only = true,
+            "--stream-solutions" => options.stream_solutions = true,
+            "--sort-by-time" => options.sort_by_time = true,
+            "--all-matches" => options.all_matches = true,
+            "--output-format" => options.output_format = get_option_output_format(&arg, args.next()),
+            "--dump-excluded" => options.dump_excluded = Some(get_option_value(&arg, args.next())),
+            "--phrase-allow" => options.phrase_allow.push(get_option_regex(&arg, args.next())),
+            "--phrase-deny" => options.phrase_deny.push(get_option_regex(&arg, args.next())),
+            "--wildcards" => options.wildcards = get_option_number(&arg, args.next()),
+            "--subset" => options.subset = true,
+            "--min-word-len" => options.min_word_len = get_option_number(&arg, args.next()),
+            "--top-words" => options.top_words = Some(get_option_number(&arg, args.next())),
+            "--max-bucket-words" => options.max_bucket_words = Some(get_option_number(&arg, args.next())),
+            "--min-phrase-len" => options.min_phrase_len = get_option_number(&arg, args.next()),
+            "--smart-prune" => options.smart_prune = true,
+            "--max-recursion" => options.max_recursion = Some(get_option_number(&arg, args.next())),
+            "--max-depth" => options.max_depth = Some(get_option_number(&arg, args.next())),
+            "--jobs" => options.jobs = Some(get_option_number(&arg, args.next())),
+            "--shuffle-roots" => options.shuffle_roots = true,
+            "--skip-root" => options.skip_roots.push(get_option_value(&arg, args.next())),
+            "--skip-roots-file" => options.skip_roots_file = Some(get_option_value(&arg, args.next())),
+            "--seed" => options.seed = Some(get_option_number(&arg, args.next())),
+            "--target-parallelism" => options.target_parallelism = get_option_number(&arg, args.next()),
+            "--top" => options.top_phrases = Some(get_option_number(&arg, args.next())),
+            "--word-scores" => options.word_scores = Some(get_option_value(&arg, args.next())),
+            "--first-letter" => options.first_letter = Some(get_option_char(&arg, args.next())),
+            "--last-letter" => options.last_letter = Some(get_option_char(&arg, args.next())),
+            "--no-solutions-exit-code" => options.no_solutions_exit_code = get_option_number(&arg, args.next()),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => {
+                eprintln!("Unknown option: {}\n\n{}", arg, USAGE);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
 
-#[derive(Clone, Debug)]
-struct AnagramSearch {
-    anagram_chars_search: HashMap<char, u32>, // The sorted anagram chars to search for
-}
+    // The environment fills in what the options didn't give, ahead of the files in resources/.
+    if options.combined.is_none() {
+        if options.target.is_none() {
+            options.target = get_env("ANAGRAM_TARGET");
+        }
+        // An index is searched instead of a wordlist.
+        if options.wordlist.is_none() && options.index.is_none() {
+            options.wordlist = get_env("ANAGRAM_WORDLIST");
+        }
+    }
 
-#[derive(Clone, Debug)]
-struct AnagramSearchLookups {
-    md5_checksums: HashSet<md5::Digest>, // Checksum to compare anagram phrases
-    anagrams_sorted_vec: Vec<String>, // Sorted anagram, to maintain order
-    anagrams_sorted_map: HashMap<String, Vec<String>>, // Sorted anagram -> Multiple Words
-    anagrams_sorted_chars: HashMap<String, HashMap<char, u32>>, // Sorted anagram -> # Characters
+    (options, verbose)
 }
 
-#[derive(Clone, Debug)]
-struct AnagramSolutionMetrics {
-    anagram_phrase_checksum: md5::Digest,
-    anagram_phrase_time: std::time::Instant,
+/// Returns the environment variable, treating an empty one as unset.
+fn get_env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.trim().is_empty())
 }
 
-/// Who doesn't like metrics? This data structure contains all of the interesting
-/// factoids that will be printed out at the end of the program's run.
-#[derive(Clone, Debug)]
-struct AnagramMetrics {
-    anagram_phrase_solution: HashMap<String, AnagramSolutionMetrics>, // The anagram phrase solutions
-    anagram_phrases_incomplete: u64, // How many incomplete phrases couldn't match the anagram characters
-    anagram_roots_exhausted: u64, // How many anagram root words have been exhaustively calculated
-    anagram_phrases_found: u64, // How many suitable phrases were found and calculated as MD5
-    anagram_phrase_max_depth: u32, // The largest number of suitable words had fit in a the anagram phrase
-    is_done: bool, // Signaling that the metrics for the root word is complete and can be tallied
+/// Returns the comma separated digests in ANAGRAM_CHECKSUMS, exiting if one isn't valid hex.
+fn get_env_checksums() -> Option<Vec<Digest>> {
+    let checksums = get_env("ANAGRAM_CHECKSUMS")?;
+    let digests = checksums.split(',')
+        .map(str::trim)
+        .filter(|checksum| !checksum.is_empty())
+        .map(|checksum| match checksum.parse() {
+            Ok(digest) => digest,
+            Err(err) => {
+                eprintln!("Invalid checksum in ANAGRAM_CHECKSUMS: {}: {}", checksum, err);
+                process::exit(EXIT_ERROR);
+            }
+        })
+        .collect();
+    Some(digests)
 }
 
-/// Retrieves the anagram phrase from resources/anagram
-/// and sorts it as an anagram phrase while omitting the spaces.
-fn get_anagram() -> (String, String) {
-    let filename = String::from("resources/anagram");
-    let f = File::open(filename).unwrap();
-    let mut f = BufReader::new(f);
-    let mut buffer = String::new();
-    let line: Result<usize, std::io::Error> = f.read_line(&mut buffer);
-
-    if line.is_ok() {
-        let mut chars: Vec<char> = buffer.chars().collect();
-        chars.retain(|&x| x != ' ');
-        chars.sort();
-        return (chars.iter().collect::<String>(), buffer)
+/// Returns the value following an option, exiting with the usage text if it's missing.
+fn get_option_value(option: &str, value: Option<String>) -> String {
+    match value {
+        Some(value) => value,
+        None => {
+            eprintln!("Missing value for option: {}\n\n{}", option, USAGE);
+            process::exit(EXIT_ERROR);
+        }
     }
-
-    return ("".to_string(), "".to_string());
 }
 
-// Get the map of sorted words to words.
-// EG:
-// abcer <-- Key
-// --brace <-- Value
-// --crabe <-- Value
-fn get_anagram_map() -> HashMap<String, HashSet<String>> {
-    let filename = String::from("resources/wordlist");
-    let f = File::open(filename).unwrap();
-    let f = BufReader::new(f);
-
-    let mut anagrams: HashMap<String, HashSet<String>> = HashMap::new();
-
-    for line in f.lines() {
-        let line = line.expect("Could not read line from file");
-        let value : String = String::from(&line);
-        let mut chars: Vec<char> = value.clone().chars().collect();
-        
-        chars.sort();
-        let anagram_sorted: String = chars.iter().collect();
-        
-        anagrams.entry(anagram_sorted).and_modify(|hs| {hs.insert(value.clone());}).or_insert(HashSet::from([value]));
+/// Returns the value following an option as a number, exiting if it isn't one.
+fn get_option_number<T: std::str::FromStr>(option: &str, value: Option<String>) -> T {
+    let value = get_option_value(option, value);
+    match value.parse() {
+        Ok(number) => number,
+        Err(_) => {
+            eprintln!("Invalid number for option {}: {}", option, value);
+            process::exit(EXIT_ERROR);
+        }
     }
-    return anagrams;
 }
 
-// Determine if the character count is within the limit of the given character sequence.
-// EG:
-// If the letter Y has 6 instances then the comparison of Y having 5 instances will return true.
-// If the ltter X has 2 instances then the comparison of X having 3 instances will return false.
-fn contains_chars(required: &HashMap<char, u32>, compare: &HashMap<char, u32>) -> bool {
-    if compare.len() > required.len() {
-        return false;
-    }
-
-    for (compare_char, compare_count) in compare {
-        let required_count = required.get(&compare_char);
-        if required_count.is_none() || compare_count > required_count.unwrap() {
-            return false;
+/// Returns the value following an option as a single character, exiting if it's longer.
+fn get_option_char(option: &str, value: Option<String>) -> char {
+    let value = get_option_value(option, value);
+    let mut chars = value.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => c,
+        _ => {
+            eprintln!("Expected a single character for option {}: {}", option, value);
+            process::exit(EXIT_ERROR);
         }
     }
-    return true;
 }
 
-// Count the characters from a string sequence
-fn count_chars(char_sequence: &String) -> HashMap<char, u32> {
-    let mut char_hash : HashMap<char, u32> = HashMap::new();
-
-    for char in char_sequence.chars() {
-        *char_hash.entry(char).or_insert(0) += 1;
+/// Returns the value following an option as a regex, exiting if it isn't a valid pattern.
+fn get_option_regex(option: &str, value: Option<String>) -> Regex {
+    let pattern = get_option_value(option, value);
+    match Regex::new(&pattern) {
+        Ok(regex) => regex,
+        Err(err) => {
+            eprintln!("Invalid pattern for option {}: {}", option, err);
+            process::exit(EXIT_ERROR);
+        }
     }
-
-    return char_hash;
 }
 
-// Add the character count to another character count
-fn add_chars(source: &mut HashMap<char, u32>, add: &HashMap<char, u32>) {
-    for (char_key, char_count) in add.iter() {
-        source.entry(*char_key).and_modify(|counter| *counter += *char_count).or_insert(*char_count);
+/// Returns the value following an option as an output format, exiting if it isn't one.
+fn get_option_output_format(option: &str, value: Option<String>) -> OutputFormat {
+    match get_option_value(option, value).parse() {
+        Ok(output_format) => output_format,
+        Err(err) => {
+            eprintln!("Invalid value for option {}: {}", option, err);
+            process::exit(EXIT_ERROR);
+        }
     }
 }
 
-// Subtract the character count from another character count.
-// This will return false if the subtracted character count is greater
-// than the source; character counts can't be negative.
-fn subtract_chars(source: &mut HashMap<char, u32>, subtract: &HashMap<char, u32>) -> bool {
-    if !contains_chars(&source, &subtract) {
-        return false;
-    }
-
-    for (char_key, char_count) in subtract.iter() {
-        source.entry(*char_key).and_modify(|counter| *counter -= *char_count);
-
-        let char_value = source.get(char_key);
-        if char_value == Some(&0) {
-            source.remove(char_key);
+/// Returns the value, or exits with what's wrong if there isn't one.
+fn exit_on_error<T>(result: Result<T, String>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(EXIT_ERROR);
         }
     }
-
-    return true;
 }
 
-// Used for metrics & reporting
-fn add_metrics(total_metric: &mut AnagramMetrics, add_metric: AnagramMetrics) {
-    total_metric.anagram_roots_exhausted += 1;
-    total_metric.anagram_phrases_incomplete += add_metric.anagram_phrases_incomplete;
-    total_metric.anagram_phrases_found += add_metric.anagram_phrases_found;
-    if total_metric.anagram_phrase_max_depth < add_metric.anagram_phrase_max_depth {
-        total_metric.anagram_phrase_max_depth = add_metric.anagram_phrase_max_depth;
-    }
-}
+/// Writes every log record to stderr as is, only installed with --verbose
+/// so the trace logging costs nothing otherwise.
+struct StderrLogger;
 
-// The entry point for the anagram phrase solution.
-fn search_anagram_phrases(mut anagram_search: AnagramSearch, anagram_search_lookups: AnagramSearchLookups) {
-    // Technical stuff to control concurrency
-    let num_cores = num_cpus::get();
-    let num_concurrent: usize = num_cores;
-    let mut count_concurrent: usize = 0;
-    let mut count_success: u32 = 0;
-    let mut metrics: AnagramMetrics = AnagramMetrics { 
-        anagram_phrase_solution: HashMap::new(),
-        anagram_phrases_incomplete: 0,
-        anagram_roots_exhausted: 0,
-        anagram_phrases_found: 0, 
-        anagram_phrase_max_depth: 0,
-        is_done: false,};
-
-    // Performance measuring metrics. Keep this immediately above the loop.
-    // For best measurements, disable the print statements until the end.
-    let start_time: Instant = Instant::now();
-
-    let (tx, rx): (Sender<AnagramMetrics>, Receiver<AnagramMetrics>) = channel();
-
-    // Loop over all sorted anagrams and insert them recursively.
-    // The actual words from the anagrams will permutate later.
-    for (current_anagram_sorted_index, current_anagram_sorted) in anagram_search_lookups.anagrams_sorted_vec.iter().enumerate() {
-        // Keep the user informed of the progress
-        println!("Processing root: {}/{}, anagram sorted: {}, len: {}",
-            current_anagram_sorted_index+1, // Use natural numbers
-            anagram_search_lookups.anagrams_sorted_vec.len(),
-            current_anagram_sorted,
-            current_anagram_sorted.len());
-
-        let current_anagram_char_count: &HashMap<char, u32> = anagram_search_lookups.anagrams_sorted_chars.get(current_anagram_sorted).unwrap();
-
-        if !subtract_chars(&mut anagram_search.anagram_chars_search, current_anagram_char_count) {
-            continue;
-        }
+static STDERR_LOGGER: StderrLogger = StderrLogger;
 
-        // The cloning is necessary for the asynchronous operations.
-        let anagram_search_clone: AnagramSearch = anagram_search.clone();
-        let anagram_search_lookups_clone: AnagramSearchLookups = anagram_search_lookups.clone();
-        let tx_clone: Sender<AnagramMetrics> = tx.clone();
-        let anagram_sorted_clone: String = current_anagram_sorted.clone();
-
-        task::spawn(async move {
-            async_traverse_anagram_phrases(
-                anagram_search_clone,
-                anagram_search_lookups_clone,
-                anagram_sorted_clone,
-                current_anagram_sorted_index,
-                tx_clone,
-            ).await});
-                        
-        add_chars(&mut anagram_search.anagram_chars_search, current_anagram_char_count);
-
-        // TODO: Code can be refactored here and complexity reduced but it's not required due to diminishing returns.
-        count_concurrent += 1;
-        if count_concurrent >= num_concurrent {
-            let metrics_received = rx.recv().unwrap();
-            metrics.anagram_phrase_solution.extend(metrics_received.anagram_phrase_solution.clone().into_iter());
-            if metrics_received.is_done {
-                count_concurrent -= 1;
-                add_metrics(&mut metrics, metrics_received);
-            } else {
-                // TODO: Complexity. This is ugly, the code can be refactored to get rid of this awful else branch
-                // Fix this later to reduce the code complexity and ugliness.
-                let solution_success = metrics_received.anagram_phrase_solution.len() as u32;
-                count_success += solution_success;
-            }
-            if count_success >= (anagram_search_lookups.md5_checksums.len() as u32) {
-                println!(
-                    "--Metrics from exhausted anagram roots--\n\
-                    - Anagram Roots Exhausted: {}\n\
-                    - Phrases Computed: {}\n\
-                    - Phrases Invalid: {}\n\
-                    - Max Phrase Length: {}\n\
-                    - Tasks in progress (no metrics reported): {}",
-                    metrics.anagram_phrases_found.to_formatted_string(&Locale::en),
-                    metrics.anagram_phrases_incomplete.to_formatted_string(&Locale::en),
-                    metrics.anagram_phrase_max_depth,
-                    metrics.anagram_roots_exhausted,
-                    count_concurrent);
-                metrics.anagram_phrase_solution.iter().for_each(|(phrase, solution_metrics)| 
-                    println!("{:?} : {}, time to find: {:?}", solution_metrics.anagram_phrase_checksum, phrase, solution_metrics.anagram_phrase_time.duration_since(start_time)));
-                // Other threads may be running, it is possible to do a graceful exit but that yields virtually no benefit
-                // and requires more complexity in the form of code. Just exit the process and call it a win.
-                process::exit(0);
-            }
-        }
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        // async-std logs every task it spawns, only the search's own records are wanted.
+        metadata.level() <= log::max_level() && metadata.target().starts_with("any_anagram")
     }
 
-    loop {
-        if count_concurrent == 0 {
-            break;
-        }
-
-        if count_concurrent >= num_concurrent {
-            let metrics_received = rx.recv().unwrap();
-            count_success += metrics_received.anagram_phrase_solution.len() as u32;
-            if metrics_received.is_done {
-                count_concurrent -= 1;
-                add_metrics(&mut metrics, metrics_received);
-            }
-            if count_success >= (anagram_search_lookups.md5_checksums.len() as u32) {
-                println!("Found all solutions in time elapsed: {:?}", start_time.elapsed());
-                println!("Phrases Computed: {}, Max Phrase Length: {}", metrics.anagram_phrases_found.to_formatted_string(&Locale::en), metrics.anagram_phrase_max_depth);
-                process::exit(0);
-            }
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}", record.args());
         }
     }
-    
-}
 
-
-async fn async_traverse_anagram_phrases(
-    mut anagram_search: AnagramSearch,
-    anagram_search_lookups: AnagramSearchLookups,
-    anagram_root: String,
-    resume_index: usize,
-    tx: Sender<AnagramMetrics>) {
-
-    let mut anagram_metrics: AnagramMetrics = AnagramMetrics{
-        anagram_phrases_incomplete: 0,
-        anagram_phrase_solution: HashMap::new(),
-        anagram_roots_exhausted: 0,
-        anagram_phrase_max_depth:0,
-        anagram_phrases_found:0,
-        is_done:false,};
-
-    let mut anagram_collected_ref: Vec<&String> = Vec::new();
-    anagram_collected_ref.push(&anagram_root);
-
-    traverse_anagram_phrases(
-            &mut anagram_search,
-            &anagram_search_lookups,
-            &mut anagram_metrics,
-            &mut anagram_collected_ref,
-            resume_index,
-            &tx);
-
-    // Send a message to the parent task that this task is done.
-    anagram_metrics.is_done = true;
-    tx.send(anagram_metrics).unwrap();
+    fn flush(&self) {}
 }
 
-fn traverse_anagram_phrases<'a>(
-                anagram_search: &mut AnagramSearch,
-                anagram_search_lookups: &'a AnagramSearchLookups,
-                anagram_metrics: &mut AnagramMetrics,
-                anagrams_collected_ref: &mut Vec<&'a String>,
-                resume_index: usize,
-                tx: &Sender<AnagramMetrics>) {
-
-    if anagram_search.anagram_chars_search.len() == 0 {
-        if anagrams_collected_ref.len() > anagram_metrics.anagram_phrase_max_depth.try_into().unwrap() {
-            anagram_metrics.anagram_phrase_max_depth = anagrams_collected_ref.len().try_into().unwrap();
-        }
-
-        let mut capacity: usize = 0;
-        for anagram_sorted in anagrams_collected_ref.iter() {
-            capacity += anagram_sorted.len() + 1;
-        }
-        let mut anagram_phrase = String::with_capacity(capacity);
-        let mut anagram_phrase_vec: Vec<&String> = Vec::new();
-        permutate_anagram_sorted(
-            anagram_search,
-            anagram_search_lookups,
-            anagram_metrics,
-            anagrams_collected_ref,
-            &mut anagram_phrase_vec,
-            &mut anagram_phrase, 
-            anagrams_collected_ref.len(),
-            tx);
-        return;
+// Load the wordlist once, then search every "phrase | checksum checksum ..."
+// line read from stdin against it until "quit" or the end of input.
+// Solutions are printed as they're found since a line may not solve them all.
+// With --target-parallelism the lines are searched in batches that run at
+// once, each with its share of the jobs, which suits piping in many small puzzles.
+fn repl(mut options: AnagramOptions) {
+    // Only the wordlist of a combined file is used, the phrases come from stdin.
+    let anagram_map = exit_on_error(load_anagram_map(&options));
+    options.stream_solutions = true;
+    if options.target_parallelism > 1 {
+        let jobs = options.jobs.unwrap_or_else(num_cpus::get);
+        options.jobs = Some((jobs / options.target_parallelism).max(1));
     }
 
-    let anagrams_sorted_vec_ref = &anagram_search_lookups.anagrams_sorted_vec;
-    for (anagram_sorted_index, anagram_sorted) in anagrams_sorted_vec_ref.iter().skip(resume_index).enumerate() {
-        let anagram_char_count = anagram_search_lookups.anagrams_sorted_chars.get(anagram_sorted).unwrap();
-        if !subtract_chars(&mut anagram_search.anagram_chars_search, &anagram_char_count) {
-            anagram_metrics.anagram_phrases_incomplete += 1;
-            continue;
-        }
+    let stdin = std::io::stdin();
+    let mut targets: Vec<(String, HashSet<Digest>)> = Vec::new();
+    loop {
+        print!("> ");
+        std::io::stdout().flush().expect("Could not write the prompt");
 
-        anagrams_collected_ref.push(anagram_sorted);
+        let mut line = String::new();
+        let done = stdin.lock().read_line(&mut line).expect("Could not read from stdin") == 0
+            || line.trim() == "quit";
 
-        traverse_anagram_phrases(
-            anagram_search,
-            anagram_search_lookups,
-            anagram_metrics,
-            anagrams_collected_ref,
-            resume_index + anagram_sorted_index,
-            &tx);
-            
-        anagrams_collected_ref.pop();
+        if !done {
+            if let Some(target) = parse_repl_line(line.trim(), &options) {
+                targets.push(target);
+            }
+        }
 
-        add_chars(&mut anagram_search.anagram_chars_search, &anagram_char_count);
+        if done || targets.len() >= options.target_parallelism {
+            search_repl_targets(&anagram_map, std::mem::take(&mut targets), &options);
+        }
+        if done {
+            break;
+        }
     }
 }
 
-fn permutate_anagram_sorted<'a>(
-    anagram_search: &mut AnagramSearch, 
-    anagram_search_lookups: &'a AnagramSearchLookups,
-    anagram_metrics: &mut AnagramMetrics,
-    anagrams_collected: &mut Vec<&String>,
-    anagram_phrase_vec: &mut Vec<&'a String>,
-    anagram_phrase: &mut String,
-    size: usize,
-    tx: &Sender<AnagramMetrics>) {
-
-    if size == 1 {
-        permutate_anagram_words(
-            anagram_search,
-            anagram_search_lookups,
-            anagram_metrics,
-            anagrams_collected,
-            anagram_phrase_vec,
-            anagram_phrase,
-            0,
-            tx);
-        return;
+// Split a REPL line into the phrase and its checksums, printing what's wrong
+// with it instead when it can't be searched.
+fn parse_repl_line(line: &str, options: &AnagramOptions) -> Option<(String, HashSet<Digest>)> {
+    if line.is_empty() {
+        return None;
     }
 
-    permutate_anagram_sorted(anagram_search,
-        anagram_search_lookups,
-        anagram_metrics,
-        anagrams_collected,
-        anagram_phrase_vec,
-        anagram_phrase,
-        size - 1,
-        tx);
-
-    for idx in 0..size-1 {        
-        if size & 1 == 1 {
-            anagrams_collected.swap(0, size-1);
-        } else {
-            anagrams_collected.swap(idx, size - 1);
+    let (phrase, checksums) = line.split_once('|').unwrap_or((line, ""));
+    let md5_checksums: Result<HashSet<Digest>, _> = checksums.split_whitespace().map(|checksum| checksum.parse()).collect();
+    let md5_checksums = match md5_checksums {
+        Ok(md5_checksums) => md5_checksums,
+        Err(err) => {
+            println!("Invalid checksum: {}", err);
+            return None;
         }
-
-        permutate_anagram_sorted(anagram_search,
-                                   anagram_search_lookups,
-                                   anagram_metrics,
-                                   anagrams_collected,
-                                   anagram_phrase_vec,
-                                   anagram_phrase,
-                                   size - 1,
-                                   tx);
+    };
+    if md5_checksums.is_empty() && !options.count_only && !options.print_digests {
+        println!("Expected: phrase | checksum checksum ...");
+        return None;
     }
 
-    if size == anagrams_collected.len() {
-        if anagrams_collected.len() & 1 == 1 {
-            anagrams_collected.swap(0, size - 1);
-        } else {
-            anagrams_collected.rotate_right(1);
+    Some((phrase.trim().to_string(), md5_checksums))
+}
+
+// Search each REPL target, all of them at once when there's more than one.
+// The threads borrow the one loaded wordlist rather than copying it.
+fn search_repl_targets(anagram_map: &HashMap<String, HashSet<String>>, targets: Vec<(String, HashSet<Digest>)>, options: &AnagramOptions) {
+    let results: Vec<(String, bool, Result<bool, String>)> = std::thread::scope(|scope| {
+        let searches: Vec<_> = targets.into_iter().map(|(phrase, md5_checksums)| {
+            scope.spawn(move || {
+                let mut options = options.clone();
+                if options.target_parallelism > 1 {
+                    options.output_prefix = format!("[{}] ", phrase);
+                }
+                let no_checksums = md5_checksums.is_empty();
+                let all_solved = search_anagram_map(anagram_map, &phrase, md5_checksums, options);
+                (phrase, no_checksums, all_solved)
+            })
+        }).collect();
+        searches.into_iter().map(|search| search.join().expect("A target's search panicked")).collect()
+    });
+
+    for (phrase, no_checksums, all_solved) in results {
+        match all_solved {
+            Ok(true) => println!("Solved every checksum: {}", phrase),
+            Ok(false) if !options.count_only && !no_checksums => println!("Not every checksum was solved: {}", phrase),
+            Ok(false) => {}
+            Err(err) => println!("Could not search {}: {}", phrase, err),
         }
     }
 }
 
-fn permutate_anagram_words<'a> (
-    anagram_search: &mut AnagramSearch,
-    anagram_search_lookups: &'a AnagramSearchLookups,
-    anagram_metrics: &mut AnagramMetrics,
-    anagrams_collected: &mut Vec<&String>,
-    anagram_phrase_vec: &mut Vec<&'a String>,
-    anagram_phrase_string: &mut String,
-    resume_idx: usize,
-    tx: &Sender<AnagramMetrics>) {
-
-    if resume_idx >= anagrams_collected.len() {
-        anagram_phrase_string.clear();
-        for word in anagram_phrase_vec {
-            anagram_phrase_string.push_str(word);
-            anagram_phrase_string.push(' ');
-        }
-        // Get rid of the very last space
-        anagram_phrase_string.pop();
+fn main() {
+    let (options, verbose) = get_options();
 
-        test_md5_checksums(anagram_phrase_string, &anagram_search_lookups.md5_checksums, anagram_metrics, tx);
-        return;
+    if verbose {
+        log::set_logger(&STDERR_LOGGER).expect("Could not set the logger");
+        log::set_max_level(log::LevelFilter::Trace);
     }
 
-    let anagram_sorted: &String = anagrams_collected.index(resume_idx);
-    let words = anagram_search_lookups.anagrams_sorted_map.get(anagram_sorted);
+    if let Err(err) = check_options(&options) {
+        eprintln!("{}\n\n{}", err, USAGE);
+        process::exit(EXIT_ERROR);
+    }
 
-    if words.is_none() {
+    if let Some(index) = &options.write_index {
+        exit_on_error(write_index(&exit_on_error(load_anagram_map(&options)), index));
         return;
     }
 
-    for word in words.unwrap().iter() {
-        anagram_phrase_vec.push(word);
-
-        permutate_anagram_words(
-                anagram_search,
-                anagram_search_lookups,
-                anagram_metrics,
-                anagrams_collected,
-                anagram_phrase_vec,
-                anagram_phrase_string,
-    resume_idx + 1,
-                tx);
-
-        anagram_phrase_vec.pop();
-    }    
-}
-
-// Test if the anagram phrase matches any of the remaining MD5 checksums
-fn test_md5_checksums(phrase: &String, md5_checksums: &HashSet<md5::Digest>, anagram_metrics: &mut AnagramMetrics, tx: &Sender<AnagramMetrics>) {
-    anagram_metrics.anagram_phrases_found += 1;
-    if md5_checksums.contains(&md5::compute(phrase)) {
-        //println!("Found phrase: {}, with md5: {:?}", phrase, md5::compute(phrase));
-        let anagram_solution_metric: AnagramSolutionMetrics = AnagramSolutionMetrics {
-            anagram_phrase_checksum: md5::compute(phrase),
-            anagram_phrase_time: std::time::Instant::now(),
-        };
-        anagram_metrics.anagram_phrase_solution.insert(phrase.clone(), anagram_solution_metric);
-        tx.send(anagram_metrics.clone()).unwrap();
+    if options.analyze_wordlist {
+        print_wordlist_analysis(&exit_on_error(load_anagram_map(&options)));
+        return;
     }
-}
 
-fn main() {
-    // Get the initial data and do some formatting
-    let anagram_map = get_anagram_map();
-    let (anagram,_search_phrase)= get_anagram();
-    let easy = hex::FromHex::from_hex("e4820b45d2277f3844eac66c903e84be").unwrap();
-    let medium = hex::FromHex::from_hex("23170acc097c24edb98fc5488ab033fe").unwrap();
-    let hard = hex::FromHex::from_hex("665e5bcb0c20062fe8abaaf4628bb154").unwrap();
-    let md5_checksums: HashSet<md5::Digest> = HashSet::from([md5::Digest(easy), md5::Digest(medium), md5::Digest(hard)]);
-    let anagram_chars : HashMap<char, u32> = count_chars(&anagram);
-
-    // Get the anagram
-    let mut anagram_chars_list : HashMap<String, HashMap<char, u32>> = HashMap::new();
-    for anagram_sorted in anagram_map.keys() {
-        anagram_chars_list.insert(anagram_sorted.clone(), count_chars(anagram_sorted));
+    if options.repl {
+        repl(options);
+        return;
     }
 
-    // Filter the anagrams that have more characters than what's provided.
-    let mut excluded_count: u32 = 0;
-    let mut anagrams_remaining_char_list: HashMap<String, HashMap<char, u32>> = HashMap::new();
-    for (anagram_sorted, anagram_counted_chars) in anagram_chars_list.iter() {
-        if !contains_chars(&anagram_chars, anagram_counted_chars) {
-            excluded_count += 1;
-        } else {
-            anagrams_remaining_char_list.insert(anagram_sorted.clone(), anagram_counted_chars.clone());
+    let mut builder = AnagramSearchBuilder::new().options(options.clone());
+    let mut no_checksums = false;
+    if options.checksum_bin.is_empty() {
+        // Printing the digests doesn't need the built in checksums, they'd only make the run fail.
+        let checksums = get_env_checksums().or_else(|| (!options.print_digests).then(|| {
+            let easy = "e4820b45d2277f3844eac66c903e84be".parse().unwrap();
+            let medium = "23170acc097c24edb98fc5488ab033fe".parse().unwrap();
+            let hard = "665e5bcb0c20062fe8abaaf4628bb154".parse().unwrap();
+            vec![easy, medium, hard]
+        })).unwrap_or_default();
+        no_checksums = checksums.is_empty();
+        for checksum in checksums {
+            builder = builder.add_checksum(checksum);
         }
     }
-
-    // Create a vector from the hashmap of anagrams. Vectors are ordered, hashmaps are random.
-    // An ordered set is necessary for optimizing the removal of repeated negative searches.
-    let mut anagram_map_vec: HashMap<String, Vec<String>> = HashMap::new();
-    let mut anagram_sorted_list_vec: Vec<String> = Vec::new();
-    for (anagram_sorted, _) in anagrams_remaining_char_list.iter() {
-        let anagram_words: &HashSet<String> = anagram_map.get(anagram_sorted).unwrap();
-        anagram_map_vec.insert(anagram_sorted.clone(), Vec::from_iter(anagram_words.clone()));
-        anagram_sorted_list_vec.push(anagram_sorted.clone());
-    }
-
-    // The sort priority is length first then by alphabetical
-    // This has a functional impact (optimization)
-    // on eliminating as many negatives from
-    // the beginning as possible.
-    anagram_sorted_list_vec.sort_by(
-    |a, b| {
-            let mut cmp = a.len().cmp(&b.len());
-            if cmp == Ordering::Equal {
-                cmp = b.cmp(a);
-            }
-            cmp
-        });
-    anagram_sorted_list_vec.reverse();
-
-    // After filtration, print the remaining anagrams to be searched
-    println!("Total: {}, Excluded: {}, Remaining: {}", anagram_chars_list.len(), excluded_count, anagram_chars_list.len() as u32 - excluded_count);
-
-    let anagram_search: AnagramSearch = AnagramSearch {
-        anagram_chars_search: anagram_chars,
-    };
-
-    // Package it all into a neat struct
-    let anagram_search_lookups: AnagramSearchLookups = AnagramSearchLookups {
-        anagrams_sorted_chars: anagrams_remaining_char_list,
-        anagrams_sorted_vec: anagram_sorted_list_vec,
-        anagrams_sorted_map: anagram_map_vec,
-        md5_checksums,
-    };
+    let anagram_search = exit_on_error(builder.build());
 
     // Start searching for the anagram phrases
-    search_anagram_phrases(anagram_search, anagram_search_lookups);
+    let all_solved = anagram_search.run();
+
+    // Counting doesn't look for the checksums, and printing the digests may have
+    // none to look for, so it isn't a failure to not solve them.
+    process::exit(if all_solved || options.count_only || no_checksums { 0 } else { options.no_solutions_exit_code });
 }
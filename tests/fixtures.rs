@@ -0,0 +1,94 @@
+use std::fs;
+use any_anagram::{digest_of, AnagramOptions, AnagramSearchBuilder, Digest};
+
+const FIXTURES: &str = "tests/fixtures";
+
+// The checksums file has a "digest phrase" line for every phrase the fixture
+// puzzle is known to have, so the expected phrases are written down next to them.
+fn fixture_checksums() -> Vec<(Digest, String)> {
+    fs::read_to_string(format!("{}/checksums", FIXTURES))
+        .expect("Could not read the fixture checksums")
+        .lines()
+        .map(|line| {
+            let (digest, phrase) = line.split_once(' ').expect("Expected \"digest phrase\"");
+            (digest.parse().expect("Invalid fixture digest"), phrase.to_string())
+        })
+        .collect()
+}
+
+fn fixture_search() -> AnagramSearchBuilder {
+    let target = fs::read_to_string(format!("{}/anagram", FIXTURES)).expect("Could not read the fixture anagram");
+    AnagramSearchBuilder::new()
+        .wordlist(&format!("{}/wordlist", FIXTURES))
+        .target(target.trim())
+}
+
+#[test]
+fn fixture_phrases_are_found() {
+    let checksums = fixture_checksums();
+    for (digest, phrase) in &checksums {
+        assert_eq!(digest, &digest_of(phrase), "the fixture checksum for {:?} is wrong", phrase);
+    }
+
+    let search = checksums.into_iter()
+        .fold(fixture_search(), |search, (digest, _)| search.add_checksum(digest))
+        .build()
+        .unwrap();
+    assert!(search.run());
+}
+
+#[test]
+fn fixture_without_the_phrase_is_unsolved() {
+    let search = fixture_search()
+        .add_checksum(digest_of("not a phrase of the fixture"))
+        .build()
+        .unwrap();
+    assert!(!search.run());
+}
+
+#[test]
+fn files_that_cant_be_written_are_errors() {
+    let options = AnagramOptions {
+        wordlist: Some(format!("{}/wordlist", FIXTURES)),
+        target: Some("tac dog".to_string()),
+        dump_excluded: Some(format!("{}/missing/excluded", FIXTURES)),
+        ..AnagramOptions::default()
+    };
+    let built = AnagramSearchBuilder::new()
+        .options(options)
+        .add_checksum(digest_of("cat dog"))
+        .build();
+    assert!(built.is_err());
+}
+
+#[test]
+fn digests_are_printed_without_checksums() {
+    assert!(fixture_search().build().is_err());
+
+    let options = AnagramOptions {
+        wordlist: Some(format!("{}/wordlist", FIXTURES)),
+        target: Some("tac dog".to_string()),
+        print_digests: true,
+        ..AnagramOptions::default()
+    };
+    let search = AnagramSearchBuilder::new().options(options).build().unwrap();
+    // There's nothing to solve, so every root is searched.
+    assert!(!search.run());
+}
+
+#[test]
+fn wordlists_that_cant_be_read_are_errors() {
+    let path = std::env::temp_dir().join(format!("any_anagram_{}_latin1", std::process::id()));
+    fs::write(&path, b"cat\ncaf\xe9\ndog\n").expect("Could not write the wordlist");
+    let options = AnagramOptions {
+        wordlist: Some(path.to_string_lossy().into_owned()),
+        target: Some("tac dog".to_string()),
+        ..AnagramOptions::default()
+    };
+    let built = AnagramSearchBuilder::new()
+        .options(options)
+        .add_checksum(digest_of("cat dog"))
+        .build();
+    fs::remove_file(&path).ok();
+    assert!(built.is_err());
+}